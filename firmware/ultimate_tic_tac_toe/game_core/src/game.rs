@@ -0,0 +1,287 @@
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Player {
+    PlayerOne = 1,
+    PlayerTwo = 2,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PlayerOrDraw {
+    Player(Player),
+    Draw,
+}
+
+impl Player {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(Player::PlayerOne),
+            2 => Some(Player::PlayerTwo),
+            _ => None,
+        }
+    }
+
+    /// The opponent of this player
+    pub fn other(self) -> Self {
+        match self {
+            Player::PlayerOne => Player::PlayerTwo,
+            Player::PlayerTwo => Player::PlayerOne,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct BoardState {
+    /// first index is sub-grid, second index is cell within sub-grid.
+    /// Both are in row-major order
+    pub board: [[Option<Player>; 9]; 9],
+    /// Row Major order (top-left, top-center, ...)
+    pub finished_grids: [Option<PlayerOrDraw>; 9],
+    pub current_player: Player,
+    /// the last two legal moves, the most recent one first
+    pub last_moves: [Option<Move>; 2],
+}
+
+/// what the user is currently selecting
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum NextUserSelection {
+    /// the user must first select a mini-grid (1..9)
+    SelectGrid,
+    /// a mini-grid is selected, user must select a cell (1..9).
+    /// The focused cell (1..9) is the one the cursor is on, if the player moves a cursor.
+    SelectCell(/*grid*/ u8, /*focused cell*/ Option<u8>),
+}
+
+/// Direction of the arrow keys or the d-pad, which move the cursor
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+/// Move the focused cell (row-major 1..9) one step, it stays within the mini-grid.
+/// Without a focused cell yet, the cursor starts in the center.
+pub fn move_focus(focus: Option<u8>, direction: Direction) -> u8 {
+    let Some(focus) = focus else {
+        return 5;
+    };
+    let row = (focus - 1) / 3;
+    let col = (focus - 1) % 3;
+    let (row, col) = match direction {
+        Direction::Up => (row.saturating_sub(1), col),
+        Direction::Down => ((row + 1).min(2), col),
+        Direction::Left => (row, col.saturating_sub(1)),
+        Direction::Right => (row, (col + 1).min(2)),
+    };
+    row * 3 + col + 1
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Move {
+    pub grid: u8, // 1..9
+    pub cell: u8, // 1..9
+}
+
+/// Coordinates on the whole 9x9 board: the column as letter a..i from the left,
+/// the row as number 1..9 from the bottom (like chess and the numpad), e.g. "e5" is the center.
+impl core::fmt::Display for Move {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let (i_grid, i_cell) = (self.grid - 1, self.cell - 1);
+        let column = (i_grid % 3) * 3 + i_cell % 3;
+        let row_from_top = (i_grid / 3) * 3 + i_cell / 3;
+        write!(f, "{}{}", (b'a' + column) as char, 9 - row_from_top)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum GameStage {
+    InProgress(BoardState, NextUserSelection),
+    /// same as InProgress, but the last move was illegal
+    IllegalMove(
+        BoardState,
+        NextUserSelection,
+        /*previous_move_attempt*/ Move,
+    ),
+    Won(Player, BoardState),
+    Draw(BoardState),
+    /// before a game starts, the player picks their color
+    ChooseColor(Player),
+}
+
+impl BoardState {
+    pub fn new() -> Self {
+        BoardState {
+            board: [[None; 9]; 9],
+            current_player: Player::PlayerOne,
+            finished_grids: [None; 9],
+            last_moves: [None; 2],
+        }
+    }
+}
+
+impl Default for BoardState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// All possible lines in a 3x3 grid, as 0-based row-major indices.
+/// Same search order as the MATLAB logic: rows, columns, diagonal, anti-diagonal.
+const LINES: [[usize; 3]; 8] = [
+    [0, 1, 2],
+    [3, 4, 5],
+    [6, 7, 8],
+    [0, 3, 6],
+    [1, 4, 7],
+    [2, 5, 8],
+    [0, 4, 8],
+    [2, 4, 6],
+];
+
+/// Find the first line in a 3x3 grid which is completely owned by `player`
+pub(crate) fn find_line(cells: &[Option<Player>; 9], player: Player) -> Option<[usize; 3]> {
+    LINES
+        .into_iter()
+        .find(|line| line.iter().all(|&i| cells[i] == Some(player)))
+}
+
+impl BoardState {
+    /// The three cells (0-based, row-major) which won the given mini-grid,
+    /// or None if the mini-grid is undecided or a draw.
+    pub fn grid_winning_line(&self, i_grid: usize) -> Option<[usize; 3]> {
+        match self.finished_grids[i_grid] {
+            Some(PlayerOrDraw::Player(p)) => find_line(&self.board[i_grid], p),
+            _ => None,
+        }
+    }
+
+    /// The three mini-grids (0-based, row-major) which won the whole game, if any.
+    pub fn winning_line(&self) -> Option<[usize; 3]> {
+        let winners = self.finished_grids.map(|g| match g {
+            Some(PlayerOrDraw::Player(p)) => Some(p),
+            _ => None,
+        });
+        find_line(&winners, Player::PlayerOne).or_else(|| find_line(&winners, Player::PlayerTwo))
+    }
+
+    /// Which mini-grids (0-based, row-major) are still undecided after the current player
+    /// takes `cell` in `grid` (both 1..9). The move itself might decide its mini-grid.
+    pub fn open_grids_after_move(&self, grid: u8, cell: u8) -> [bool; 9] {
+        let i_grid = grid as usize - 1;
+        let mut cells = self.board[i_grid];
+        cells[cell as usize - 1] = Some(self.current_player);
+        let decided =
+            find_line(&cells, self.current_player).is_some() || cells.iter().all(|c| c.is_some());
+
+        core::array::from_fn(|i| self.finished_grids[i].is_none() && !(i == i_grid && decided))
+    }
+
+    /// The mini-grid (1..9) the opponent has to play in after the current player takes `cell`
+    /// in `grid`, or None if that mini-grid is decided and the opponent can choose freely.
+    pub fn move_target(&self, grid: u8, cell: u8) -> Option<u8> {
+        self.open_grids_after_move(grid, cell)[cell as usize - 1].then_some(cell)
+    }
+
+    /// How many moves ago (0 is the most recent move) the cell (0-based) was played,
+    /// if it is one of the last two moves
+    pub fn move_age(&self, i_grid: usize, i_cell: usize) -> Option<usize> {
+        self.last_moves.iter().position(|m| {
+            m.is_some_and(|m| m.grid as usize - 1 == i_grid && m.cell as usize - 1 == i_cell)
+        })
+    }
+
+    pub fn is_draw(&self) -> bool {
+        // it's a draw if all 81 cells are filled, or all sub grids are finished
+        for i_board in 0..9 {
+            for i_cell in 0..9 {
+                if self.board[i_board][i_cell].is_none() {
+                    return false;
+                }
+            }
+        }
+        // Check if all sub-grids are finished
+        for i in 0..9 {
+            if self.finished_grids[i].is_none() {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod test_find_line {
+    use super::{Player, find_line};
+
+    #[test]
+    fn test_find_line() {
+        let mut cells = [None; 9];
+        assert_eq!(find_line(&cells, Player::PlayerOne), None);
+
+        cells[2] = Some(Player::PlayerOne);
+        cells[4] = Some(Player::PlayerOne);
+        cells[6] = Some(Player::PlayerOne);
+        assert_eq!(find_line(&cells, Player::PlayerOne), Some([2, 4, 6]));
+        assert_eq!(find_line(&cells, Player::PlayerTwo), None);
+
+        cells[1] = Some(Player::PlayerTwo);
+        cells[7] = Some(Player::PlayerTwo);
+        cells[4] = Some(Player::PlayerTwo);
+        assert_eq!(find_line(&cells, Player::PlayerOne), None);
+        assert_eq!(find_line(&cells, Player::PlayerTwo), Some([1, 4, 7]));
+    }
+}
+
+#[cfg(test)]
+mod test_move_target {
+    use crate::{
+        game::{BoardState, Player},
+        samples::board_from_rows,
+    };
+
+    #[test]
+    fn test_move_target() {
+        let board = board_from_rows(
+            [
+                "XXX O.. ...",
+                "... .X. ...",
+                "... .X. ...",
+                "... ... ...",
+                "... ... ...",
+                "... ... ...",
+                "... ... ...",
+                "... ... ...",
+                "... ... ...",
+            ],
+            Player::PlayerOne,
+        );
+        // an open mini-grid
+        assert_eq!(board.move_target(2, 3), Some(3));
+        // the top-left mini-grid is already won
+        assert_eq!(board.move_target(2, 1), None);
+        // the move wins the top-center mini-grid, which is also the target
+        assert_eq!(board.move_target(2, 2), None);
+        // the same move by player two doesn't decide it
+        let board = BoardState {
+            current_player: Player::PlayerTwo,
+            ..board
+        };
+        assert_eq!(board.move_target(2, 2), Some(2));
+    }
+}
+
+#[cfg(test)]
+mod test_move_focus {
+    use super::{Direction, move_focus};
+
+    #[test]
+    fn test_move_focus() {
+        assert_eq!(move_focus(None, Direction::Left), 5);
+        assert_eq!(move_focus(Some(5), Direction::Up), 2);
+        assert_eq!(move_focus(Some(5), Direction::Right), 6);
+        // stays within the mini-grid
+        assert_eq!(move_focus(Some(3), Direction::Right), 3);
+        assert_eq!(move_focus(Some(8), Direction::Down), 8);
+        assert_eq!(move_focus(Some(4), Direction::Left), 4);
+    }
+}
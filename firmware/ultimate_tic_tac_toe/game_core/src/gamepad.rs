@@ -0,0 +1,135 @@
+//! Controls of a USB gamepad or joystick: the direction of the d-pad (hat switch) or the stick,
+//! and the buttons. The reports are decoded by `hid_descriptor::ReportDescriptor`.
+
+/// A direction or a button, buttons are numbered from 1 like in the HID usages
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GamepadControl {
+    Up,
+    Down,
+    Left,
+    Right,
+    Button(u8),
+}
+
+/// A control was pressed or released
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GamepadEvent {
+    Pressed(GamepadControl),
+    Released(GamepadControl),
+}
+
+/// buttons which fit into `GamepadControls` besides the four directions
+pub const MAX_BUTTONS: u8 = 28;
+
+impl GamepadControl {
+    fn bit(self) -> Option<u32> {
+        match self {
+            GamepadControl::Up => Some(0),
+            GamepadControl::Down => Some(1),
+            GamepadControl::Left => Some(2),
+            GamepadControl::Right => Some(3),
+            GamepadControl::Button(n) if (1..=MAX_BUTTONS).contains(&n) => Some(3 + n as u32),
+            GamepadControl::Button(_) => None,
+        }
+    }
+
+    fn from_bit(bit: u32) -> Self {
+        match bit {
+            0 => GamepadControl::Up,
+            1 => GamepadControl::Down,
+            2 => GamepadControl::Left,
+            3 => GamepadControl::Right,
+            _ => GamepadControl::Button((bit - 3) as u8),
+        }
+    }
+}
+
+/// The controls held in one report
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct GamepadControls(u32);
+
+impl GamepadControls {
+    /// Hold `control`, buttons above `MAX_BUTTONS` are ignored
+    pub fn hold(&mut self, control: GamepadControl) {
+        if let Some(bit) = control.bit() {
+            self.0 |= 1 << bit;
+        }
+    }
+
+    pub fn is_held(&self, control: GamepadControl) -> bool {
+        control.bit().is_some_and(|bit| self.0 & (1 << bit) != 0)
+    }
+}
+
+/// The controls held on one gamepad, updated by each report
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct GamepadState {
+    held: GamepadControls,
+}
+
+impl GamepadState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Apply the next report and call `on_event` for every control which changed since the
+    /// last one, releases first. Holding a control doesn't repeat it.
+    pub fn update(&mut self, controls: GamepadControls, mut on_event: impl FnMut(GamepadEvent)) {
+        let released = self.held.0 & !controls.0;
+        for bit in (0..32).filter(|bit| released & (1 << bit) != 0) {
+            on_event(GamepadEvent::Released(GamepadControl::from_bit(bit)));
+        }
+        let pressed = controls.0 & !self.held.0;
+        for bit in (0..32).filter(|bit| pressed & (1 << bit) != 0) {
+            on_event(GamepadEvent::Pressed(GamepadControl::from_bit(bit)));
+        }
+        self.held = controls;
+    }
+}
+
+#[cfg(test)]
+mod test_gamepad {
+    extern crate std;
+    use std::vec::Vec;
+
+    use super::{GamepadControl, GamepadControls, GamepadEvent, GamepadState};
+
+    fn controls(held: &[GamepadControl]) -> GamepadControls {
+        let mut controls = GamepadControls::default();
+        for &control in held {
+            controls.hold(control);
+        }
+        controls
+    }
+
+    #[test]
+    fn test_presses() {
+        use GamepadControl::*;
+        use GamepadEvent::{Pressed, Released};
+        let mut state = GamepadState::new();
+        let mut events = Vec::new();
+        for held in [
+            &[Up][..],
+            &[Up],
+            &[Up, Button(1)],
+            &[Button(1), Right],
+            &[],
+            &[Button(28), Button(29)],
+        ] {
+            state.update(controls(held), |event| events.push(event));
+        }
+        // button 29 doesn't fit
+        assert_eq!(
+            events,
+            [
+                Pressed(Up),
+                Pressed(Button(1)),
+                Released(Up),
+                Pressed(Right),
+                Released(Right),
+                Released(Button(1)),
+                Pressed(Button(28)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,727 @@
+//! Parser for HID report descriptors, to decode the key reports of keyboards which don't use
+//! the 8 byte boot layout: NKRO keyboards with a bitmap of all keys, composite devices with
+//! report IDs, or numpads which only support the report protocol.
+//! It also decodes the reports of gamepads and joysticks, which have no boot layout at all.
+//!
+//! Only the input fields for keys, buttons, the stick and the hat switch are kept, everything
+//! else is skipped.
+
+use crate::{
+    gamepad::{GamepadControl, GamepadControls},
+    keyboard::{FIRST_MODIFIER_USAGE, KeyboardReport},
+};
+
+/// usage page of the stick, the hat switch and the application collections
+const GENERIC_DESKTOP_PAGE: u16 = 0x01;
+/// usage page of the keys
+const KEYBOARD_PAGE: u16 = 0x07;
+/// usage page of the lock LEDs of a keyboard
+const LED_PAGE: u16 = 0x08;
+/// usage page of the gamepad buttons
+const BUTTON_PAGE: u16 = 0x09;
+/// generic desktop usages
+const JOYSTICK: u16 = 0x04;
+const GAMEPAD: u16 = 0x05;
+const X_AXIS: u16 = 0x30;
+const Y_AXIS: u16 = 0x31;
+const HAT_SWITCH: u16 = 0x39;
+/// fields kept per descriptor, a NKRO keyboard needs three (modifiers, bitmap, boot keys)
+const MAX_FIELDS: usize = 16;
+/// explicitly listed usages kept per main item
+const MAX_USAGES: usize = 16;
+/// depth of the Push/Pop stack of the global items
+const MAX_PUSH_DEPTH: usize = 4;
+/// usage in all six key slots of a boot report, when more keys are held than fit into it
+const ERROR_ROLL_OVER: u8 = 0x01;
+
+/// Why a descriptor couldn't be parsed
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DescriptorError {
+    /// an item is cut off at the end of the descriptor
+    Truncated,
+    /// more key, button and axis fields than `MAX_FIELDS`
+    TooManyFields,
+    /// Push or Pop without a matching counterpart, or nested too deep
+    BadPushPop,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum FieldKind {
+    /// each element holds the index of a held key, `usage_min` for the logical minimum
+    Array { usage_min: u16, logical_min: i32 },
+    /// one element per usage in a row starting at `usage_min`: non-zero if a key or button is
+    /// held, or the position of an axis within the logical range
+    Variable {
+        usage_min: u16,
+        logical_min: i32,
+        logical_max: i32,
+    },
+}
+
+/// Input field with keys, buttons or axes
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Field {
+    /// 0 if the device doesn't use report IDs
+    report_id: u8,
+    usage_page: u16,
+    /// position of the first element, after the report ID
+    bit_offset: u32,
+    bit_size: u32,
+    count: u32,
+    kind: FieldKind,
+}
+
+/// The global items, which stay valid until they are changed
+#[derive(Clone, Copy, Default)]
+struct Globals {
+    usage_page: u16,
+    logical_min: i32,
+    logical_max: i32,
+    report_size: u32,
+    report_count: u32,
+    report_id: u8,
+}
+
+/// The local items, which only apply to the next main item
+#[derive(Clone, Copy, Default)]
+struct Locals {
+    /// (usage page, usage) of explicitly listed usages
+    usages: [(u16, u16); MAX_USAGES],
+    usage_count: usize,
+    usage_min: Option<(u16, u16)>,
+}
+
+/// The key, button and axis fields of the input reports of one HID interface
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ReportDescriptor {
+    fields: [Option<Field>; MAX_FIELDS],
+    uses_report_ids: bool,
+    /// it has a joystick or gamepad application collection
+    gamepad: bool,
+    /// report ID of the output report with the LEDs, if there is one
+    led_report_id: Option<u8>,
+}
+
+impl ReportDescriptor {
+    /// Parse the raw report descriptor, as sent by the device
+    pub fn parse(descriptor: &[u8]) -> Result<Self, DescriptorError> {
+        let mut parsed = ReportDescriptor {
+            fields: [None; MAX_FIELDS],
+            uses_report_ids: false,
+            gamepad: false,
+            led_report_id: None,
+        };
+        let mut globals = Globals::default();
+        let mut stack = [Globals::default(); MAX_PUSH_DEPTH];
+        let mut stack_len = 0;
+        let mut locals = Locals::default();
+        // next free bit of the input report with each ID
+        let mut input_offsets = [0u32; 256];
+        let mut field_count = 0;
+
+        let mut i = 0;
+        while i < descriptor.len() {
+            let prefix = descriptor[i];
+            if prefix == 0xFE {
+                // long item: size and tag follow, its data is skipped
+                let size = *descriptor.get(i + 1).ok_or(DescriptorError::Truncated)? as usize;
+                i += 3 + size;
+                continue;
+            }
+            let size = match prefix & 0x03 {
+                3 => 4,
+                size => size as usize,
+            };
+            let data = descriptor
+                .get(i + 1..i + 1 + size)
+                .ok_or(DescriptorError::Truncated)?;
+            i += 1 + size;
+            let unsigned = data
+                .iter()
+                .rev()
+                .fold(0u32, |value, &byte| (value << 8) | byte as u32);
+            // sign extend from the size of the item
+            let signed = match size {
+                1 => unsigned as u8 as i8 as i32,
+                2 => unsigned as u16 as i16 as i32,
+                _ => unsigned as i32,
+            };
+
+            match prefix & 0xFC {
+                // Input
+                0x80 => {
+                    let id = globals.report_id as usize;
+                    let constant = unsigned & 0x01 != 0;
+                    let variable = unsigned & 0x02 != 0;
+                    let field = Field {
+                        report_id: globals.report_id,
+                        usage_page: 0,
+                        bit_offset: input_offsets[id],
+                        bit_size: globals.report_size,
+                        count: globals.report_count,
+                        kind: FieldKind::Array {
+                            usage_min: 0,
+                            logical_min: 0,
+                        },
+                    };
+                    input_offsets[id] = input_offsets[id]
+                        .saturating_add(globals.report_size.saturating_mul(globals.report_count));
+                    if !constant {
+                        for field in input_fields(field, variable, &globals, &locals) {
+                            let slot = parsed
+                                .fields
+                                .get_mut(field_count)
+                                .ok_or(DescriptorError::TooManyFields)?;
+                            *slot = Some(field);
+                            field_count += 1;
+                        }
+                    }
+                    locals = Locals::default();
+                }
+                // Collection
+                0xA0 => {
+                    // an application collection (type 1) with the usage of the whole device
+                    let usage = locals.usages[..locals.usage_count].first();
+                    if unsigned == 0x01
+                        && usage.is_some_and(|&(page, usage)| {
+                            page == GENERIC_DESKTOP_PAGE && (usage == JOYSTICK || usage == GAMEPAD)
+                        })
+                    {
+                        parsed.gamepad = true;
+                    }
+                    locals = Locals::default();
+                }
+                // Output
+                0x90 => {
+                    let usages = locals.usages[..locals.usage_count].iter();
+                    if usages
+                        .chain(&locals.usage_min)
+                        .any(|&(page, _)| page == LED_PAGE)
+                    {
+                        parsed.led_report_id.get_or_insert(globals.report_id);
+                    }
+                    locals = Locals::default();
+                }
+                // Feature, End Collection
+                0xB0 | 0xC0 => locals = Locals::default(),
+                // Usage Page
+                0x04 => globals.usage_page = unsigned as u16,
+                // Logical Minimum
+                0x14 => globals.logical_min = signed,
+                // Logical Maximum
+                0x24 => globals.logical_max = signed,
+                // Report Size
+                0x74 => globals.report_size = unsigned,
+                // Report ID
+                0x84 => {
+                    globals.report_id = unsigned as u8;
+                    parsed.uses_report_ids = true;
+                }
+                // Report Count
+                0x94 => globals.report_count = unsigned,
+                // Push
+                0xA4 => {
+                    *stack
+                        .get_mut(stack_len)
+                        .ok_or(DescriptorError::BadPushPop)? = globals;
+                    stack_len += 1;
+                }
+                // Pop
+                0xB4 => {
+                    stack_len = stack_len
+                        .checked_sub(1)
+                        .ok_or(DescriptorError::BadPushPop)?;
+                    globals = stack[stack_len];
+                }
+                // Usage
+                0x08 if locals.usage_count < MAX_USAGES => {
+                    locals.usages[locals.usage_count] = extended_usage(&globals, size, unsigned);
+                    locals.usage_count += 1;
+                }
+                // Usage Minimum
+                0x18 => locals.usage_min = Some(extended_usage(&globals, size, unsigned)),
+                // everything else (Usage Maximum, units, ...) isn't needed
+                _ => {}
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn fields(&self) -> impl Iterator<Item = &Field> {
+        self.fields.iter().flatten()
+    }
+
+    /// True if any input report contains keys
+    pub fn has_keys(&self) -> bool {
+        self.fields().any(|field| field.usage_page == KEYBOARD_PAGE)
+    }
+
+    /// Report ID of the output report with the lock LEDs, 0 if the device doesn't use report
+    /// IDs, None without LEDs. The LEDs are assumed to be in the first bits, like on a boot
+    /// keyboard.
+    pub fn led_report_id(&self) -> Option<u8> {
+        self.led_report_id
+    }
+
+    /// True if the device is a gamepad or joystick with buttons
+    pub fn is_gamepad(&self) -> bool {
+        self.gamepad && self.fields().any(|field| field.usage_page == BUTTON_PAGE)
+    }
+
+    /// The report ID and the data after it
+    fn split_report_id<'a>(&self, report: &'a [u8]) -> Option<(u8, &'a [u8])> {
+        match self.uses_report_ids {
+            true => Some((*report.first()?, &report[1..])),
+            false => Some((0, report)),
+        }
+    }
+
+    /// Decode an input report into the layout of a boot report, None if this report doesn't
+    /// contain keys (e.g. media keys with another report ID).
+    /// If more keys are held than fit into the 6 slots, they are all set to ErrorRollOver,
+    /// like a boot keyboard does.
+    pub fn decode(&self, report: &[u8]) -> Option<KeyboardReport> {
+        let (report_id, data) = self.split_report_id(report)?;
+
+        let mut decoded = KeyboardReport::default();
+        let mut key_count = 0;
+        let mut found = false;
+        let fields = self
+            .fields()
+            .filter(|field| field.usage_page == KEYBOARD_PAGE);
+        for field in fields.filter(|field| field.report_id == report_id) {
+            found = true;
+            for (element, value) in field.values(data) {
+                let usage = match field.kind {
+                    FieldKind::Array {
+                        usage_min,
+                        logical_min,
+                    } => (value - logical_min + usage_min as i32) as u32,
+                    FieldKind::Variable { usage_min, .. } if value != 0 => {
+                        usage_min as u32 + element
+                    }
+                    FieldKind::Variable { .. } => continue,
+                };
+
+                match usage {
+                    // no key, in an array field
+                    0 => {}
+                    _ if (FIRST_MODIFIER_USAGE as u32..FIRST_MODIFIER_USAGE as u32 + 8)
+                        .contains(&usage) =>
+                    {
+                        decoded.modifier |= 1 << (usage - FIRST_MODIFIER_USAGE as u32);
+                    }
+                    1..=0xFF => {
+                        let usage = usage as u8;
+                        if decoded.keycode.contains(&usage) {
+                            continue;
+                        }
+                        if key_count < decoded.keycode.len() {
+                            decoded.keycode[key_count] = usage;
+                        }
+                        key_count += 1;
+                    }
+                    // outside of the keyboard page
+                    _ => {}
+                }
+            }
+        }
+        if key_count > decoded.keycode.len() {
+            decoded.keycode = [ERROR_ROLL_OVER; 6];
+        }
+        found.then_some(decoded)
+    }
+
+    /// Decode an input report of a gamepad, None if this report has no buttons or axes.
+    /// The stick counts as a direction when it is pushed more than halfway.
+    pub fn decode_gamepad(&self, report: &[u8]) -> Option<GamepadControls> {
+        let (report_id, data) = self.split_report_id(report)?;
+
+        let mut controls = GamepadControls::default();
+        let mut found = false;
+        let fields = self
+            .fields()
+            .filter(|field| field.usage_page != KEYBOARD_PAGE && field.report_id == report_id);
+        for field in fields {
+            let FieldKind::Variable {
+                usage_min,
+                logical_min,
+                logical_max,
+            } = field.kind
+            else {
+                continue;
+            };
+            found = true;
+            for (element, value) in field.values(data) {
+                let usage = usage_min as u32 + element;
+                let held: &[GamepadControl] = match (field.usage_page, usage as u16) {
+                    (BUTTON_PAGE, _) if value != 0 => {
+                        &[GamepadControl::Button(usage.min(u8::MAX as u32) as u8)]
+                    }
+                    (GENERIC_DESKTOP_PAGE, X_AXIS) => match axis(value, logical_min, logical_max) {
+                        -1 => &[GamepadControl::Left],
+                        1 => &[GamepadControl::Right],
+                        _ => &[],
+                    },
+                    (GENERIC_DESKTOP_PAGE, Y_AXIS) => match axis(value, logical_min, logical_max) {
+                        -1 => &[GamepadControl::Up],
+                        1 => &[GamepadControl::Down],
+                        _ => &[],
+                    },
+                    // clockwise from north, a value outside of the range means centered
+                    (GENERIC_DESKTOP_PAGE, HAT_SWITCH) => match value - logical_min {
+                        0 => &[GamepadControl::Up],
+                        1 => &[GamepadControl::Up, GamepadControl::Right],
+                        2 => &[GamepadControl::Right],
+                        3 => &[GamepadControl::Down, GamepadControl::Right],
+                        4 => &[GamepadControl::Down],
+                        5 => &[GamepadControl::Down, GamepadControl::Left],
+                        6 => &[GamepadControl::Left],
+                        7 => &[GamepadControl::Up, GamepadControl::Left],
+                        _ => &[],
+                    },
+                    _ => &[],
+                };
+                for &control in held {
+                    controls.hold(control);
+                }
+            }
+        }
+        found.then_some(controls)
+    }
+}
+
+impl Field {
+    /// The elements of this field in `data` with their index, signed if the logical minimum
+    /// is negative. Stops where the report ends.
+    fn values<'a>(&'a self, data: &'a [u8]) -> impl Iterator<Item = (u32, i32)> + 'a {
+        let signed = match self.kind {
+            FieldKind::Array { logical_min, .. } | FieldKind::Variable { logical_min, .. } => {
+                logical_min < 0
+            }
+        };
+        (0..self.count).map_while(move |element| {
+            let offset = self
+                .bit_offset
+                .saturating_add(element.saturating_mul(self.bit_size));
+            let value = read_bits(data, offset, self.bit_size)?;
+            let value = match signed && (1..32).contains(&self.bit_size) {
+                // sign extend from the size of the field
+                true => ((value << (32 - self.bit_size)) as i32) >> (32 - self.bit_size),
+                false => value as i32,
+            };
+            Some((element, value))
+        })
+    }
+}
+
+/// Direction of an axis: -1 or 1 when it is pushed more than halfway from the center
+fn axis(value: i32, logical_min: i32, logical_max: i32) -> i32 {
+    let range = logical_max as i64 - logical_min as i64;
+    // four times the distance from the minimum, to compare with the quarters of the range
+    let position = 4 * (value as i64 - logical_min as i64);
+    match range > 0 {
+        true if position < range => -1,
+        true if position > 3 * range => 1,
+        _ => 0,
+    }
+}
+
+/// (usage page, usage) of a Usage or Usage Minimum item, a 4 byte item contains its own page
+fn extended_usage(globals: &Globals, size: usize, data: u32) -> (u16, u16) {
+    match size {
+        4 => ((data >> 16) as u16, data as u16),
+        _ => (globals.usage_page, data as u16),
+    }
+}
+
+/// True for the usages which are kept: keys, buttons, the stick and the hat switch.
+/// Only keys can be in an array, the others are variable.
+fn is_input_usage(page: u16, usage: u16, variable: bool) -> bool {
+    match page {
+        KEYBOARD_PAGE => true,
+        BUTTON_PAGE => variable,
+        GENERIC_DESKTOP_PAGE => variable && matches!(usage, X_AXIS | Y_AXIS | HAT_SWITCH),
+        _ => false,
+    }
+}
+
+/// The kept fields of an Input item, `template` has everything but the usages.
+/// Up to `MAX_USAGES` fields for a variable item with explicitly listed usages.
+fn input_fields(
+    template: Field,
+    variable: bool,
+    globals: &Globals,
+    locals: &Locals,
+) -> impl Iterator<Item = Field> {
+    let logical_min = globals.logical_min;
+    let logical_max = globals.logical_max;
+    // either a range, or the listed usages (the last one repeats for the remaining elements)
+    let range = locals
+        .usage_min
+        .or_else(|| (!variable).then_some(locals.usages[0]))
+        .filter(|_| locals.usage_min.is_some() || locals.usage_count > 0);
+    let single = range.map(|(page, usage_min)| {
+        let kind = match variable {
+            true => FieldKind::Variable {
+                usage_min,
+                logical_min,
+                logical_max,
+            },
+            false => FieldKind::Array {
+                usage_min,
+                logical_min,
+            },
+        };
+        let field = Field {
+            usage_page: page,
+            kind,
+            ..template
+        };
+        (usage_min, field)
+    });
+
+    let listed = (0..locals.usage_count)
+        .filter(move |_| range.is_none())
+        .map(move |i| {
+            let (page, usage) = locals.usages[i];
+            let last = i + 1 == locals.usage_count;
+            let count = match last {
+                true => template.count.saturating_sub(i as u32),
+                false => 1,
+            };
+            let field = Field {
+                usage_page: page,
+                bit_offset: template.bit_offset + i as u32 * template.bit_size,
+                count,
+                kind: FieldKind::Variable {
+                    usage_min: usage,
+                    logical_min,
+                    logical_max,
+                },
+                ..template
+            };
+            (usage, field)
+        });
+
+    single
+        .into_iter()
+        .chain(listed)
+        .filter(move |&(usage, field)| {
+            is_input_usage(field.usage_page, usage, variable) && field.count > 0
+        })
+        .map(|(_, field)| field)
+}
+
+/// Read `size` bits (at most 32) starting at bit `offset`, least significant bit first
+fn read_bits(data: &[u8], offset: u32, size: u32) -> Option<u32> {
+    let mut value = 0u32;
+    for bit in 0..size.min(32) {
+        let position = offset + bit;
+        let byte = *data.get((position / 8) as usize)?;
+        value |= (((byte >> (position % 8)) & 1) as u32) << bit;
+    }
+    Some(value)
+}
+
+#[cfg(test)]
+mod test_hid_descriptor {
+    extern crate std;
+    use std::vec::Vec;
+
+    use super::{DescriptorError, ReportDescriptor};
+    use crate::{
+        gamepad::{GamepadControl, GamepadControls},
+        keyboard::KeyboardReport,
+    };
+
+    /// The boot keyboard of the HID specification (appendix E.6), used as is by most
+    /// simple keyboards
+    const BOOT_KEYBOARD: &[u8] = &[
+        0x05, 0x01, 0x09, 0x06, 0xA1, 0x01, 0x05, 0x07, 0x19, 0xE0, 0x29, 0xE7, 0x15, 0x00, 0x25,
+        0x01, 0x75, 0x01, 0x95, 0x08, 0x81, 0x02, 0x95, 0x01, 0x75, 0x08, 0x81, 0x01, 0x95, 0x05,
+        0x75, 0x01, 0x05, 0x08, 0x19, 0x01, 0x29, 0x05, 0x91, 0x02, 0x95, 0x01, 0x75, 0x03, 0x91,
+        0x01, 0x95, 0x06, 0x75, 0x08, 0x15, 0x00, 0x25, 0x65, 0x05, 0x07, 0x19, 0x00, 0x29, 0x65,
+        0x81, 0x00, 0xC0,
+    ];
+
+    /// The NKRO report of QMK firmware with report ID 6: the modifiers and a bitmap with
+    /// one bit per key, followed by the LED output report
+    const QMK_NKRO: &[u8] = &[
+        0x05, 0x01, 0x09, 0x06, 0xA1, 0x01, 0x85, 0x06, 0x05, 0x07, 0x19, 0xE0, 0x29, 0xE7, 0x15,
+        0x00, 0x25, 0x01, 0x95, 0x08, 0x75, 0x01, 0x81, 0x02, 0x05, 0x07, 0x19, 0x00, 0x29, 0xEF,
+        0x15, 0x00, 0x25, 0x01, 0x96, 0xF0, 0x00, 0x75, 0x01, 0x81, 0x02, 0x05, 0x08, 0x19, 0x01,
+        0x29, 0x05, 0x95, 0x05, 0x75, 0x01, 0x91, 0x02, 0xC0,
+    ];
+
+    /// A wireless numpad with keys (report ID 1) and media keys (report ID 2) on one
+    /// interface, the modifiers are listed one by one
+    const NUMPAD_WITH_MEDIA_KEYS: &[u8] = &[
+        0x05, 0x01, 0x09, 0x06, 0xA1, 0x01, 0x85, 0x01, 0x05, 0x07, 0x09, 0xE0, 0x09, 0xE1, 0x09,
+        0xE2, 0x09, 0xE3, 0x09, 0xE4, 0x09, 0xE5, 0x09, 0xE6, 0x09, 0xE7, 0x15, 0x00, 0x25, 0x01,
+        0x75, 0x01, 0x95, 0x08, 0x81, 0x02, 0x95, 0x04, 0x75, 0x08, 0x15, 0x00, 0x26, 0xFF, 0x00,
+        0x19, 0x00, 0x2A, 0xFF, 0x00, 0x81, 0x00, 0xC0, 0x05, 0x0C, 0x09, 0x01, 0xA1, 0x01, 0x85,
+        0x02, 0x15, 0x00, 0x26, 0x3C, 0x02, 0x19, 0x00, 0x2A, 0x3C, 0x02, 0x75, 0x10, 0x95, 0x01,
+        0x81, 0x00, 0xC0,
+    ];
+
+    /// A cheap USB gamepad: five 8 bit axes (X, Y, Z, Z, Rz), a hat switch with a null state,
+    /// twelve buttons and eight vendor defined bits
+    const GAMEPAD: &[u8] = &[
+        0x05, 0x01, 0x09, 0x04, 0xA1, 0x01, 0xA1, 0x02, 0x75, 0x08, 0x95, 0x05, 0x15, 0x00, 0x26,
+        0xFF, 0x00, 0x35, 0x00, 0x46, 0xFF, 0x00, 0x09, 0x30, 0x09, 0x31, 0x09, 0x32, 0x09, 0x32,
+        0x09, 0x35, 0x81, 0x02, 0x75, 0x04, 0x95, 0x01, 0x25, 0x07, 0x46, 0x3B, 0x01, 0x65, 0x14,
+        0x09, 0x39, 0x81, 0x42, 0x65, 0x00, 0x75, 0x01, 0x95, 0x0C, 0x25, 0x01, 0x45, 0x01, 0x05,
+        0x09, 0x19, 0x01, 0x29, 0x0C, 0x81, 0x02, 0x06, 0x00, 0xFF, 0x75, 0x01, 0x95, 0x08, 0x25,
+        0x01, 0x45, 0x01, 0x09, 0x01, 0x81, 0x02, 0xC0, 0xC0,
+    ];
+
+    fn report(modifier: u8, keycode: [u8; 6]) -> KeyboardReport {
+        KeyboardReport { modifier, keycode }
+    }
+
+    #[test]
+    fn test_boot_keyboard() {
+        let descriptor = ReportDescriptor::parse(BOOT_KEYBOARD).unwrap();
+        assert!(descriptor.has_keys());
+        assert_eq!(descriptor.led_report_id(), Some(0));
+        // left shift + numpad 5 + numpad 6, the same as the boot layout
+        let bytes = [0x02, 0x00, 0x5D, 0x5E, 0, 0, 0, 0];
+        assert_eq!(
+            descriptor.decode(&bytes),
+            KeyboardReport::from_bytes(&bytes)
+        );
+    }
+
+    #[test]
+    fn test_nkro_bitmap() {
+        let descriptor = ReportDescriptor::parse(QMK_NKRO).unwrap();
+        assert_eq!(descriptor.led_report_id(), Some(6));
+        // report ID, modifiers, then 30 bytes of key bits: 0x1E (1) is bit 6 of byte 3,
+        // 0x59 (numpad 1) is bit 1 of byte 11
+        let mut bytes = [0u8; 32];
+        bytes[0] = 0x06;
+        bytes[1] = 0x01;
+        bytes[2 + 3] = 1 << 6;
+        bytes[2 + 11] = 1 << 1;
+        assert_eq!(
+            descriptor.decode(&bytes),
+            Some(report(0x01, [0x1E, 0x59, 0, 0, 0, 0]))
+        );
+
+        // more than six keys: rollover, like a boot keyboard
+        bytes[2 + 4] = 0xFF;
+        assert_eq!(descriptor.decode(&bytes), Some(report(0x01, [0x01; 6])));
+
+        // another report ID
+        assert_eq!(descriptor.decode(&[0x01, 0x00]), None);
+    }
+
+    #[test]
+    fn test_report_ids() {
+        let descriptor = ReportDescriptor::parse(NUMPAD_WITH_MEDIA_KEYS).unwrap();
+        assert_eq!(descriptor.led_report_id(), None);
+        // right alt and numpad enter
+        assert_eq!(
+            descriptor.decode(&[0x01, 0x40, 0x58, 0, 0, 0]),
+            Some(report(0x40, [0x58, 0, 0, 0, 0, 0]))
+        );
+        // volume up on the consumer page isn't a key
+        assert_eq!(descriptor.decode(&[0x02, 0xE9, 0x00]), None);
+        // a report cut off after the first key
+        assert_eq!(
+            descriptor.decode(&[0x01, 0x00, 0x5A]),
+            Some(report(0x00, [0x5A, 0, 0, 0, 0, 0]))
+        );
+    }
+
+    #[test]
+    fn test_malformed() {
+        // a mouse has no keys
+        let mouse = [
+            0x05, 0x01, 0x09, 0x02, 0xA1, 0x01, 0x05, 0x09, 0x19, 0x01, 0x29, 0x03, 0x95, 0x03,
+            0x75, 0x01, 0x81, 0x02, 0xC0,
+        ];
+        let mouse = ReportDescriptor::parse(&mouse).unwrap();
+        assert!(!mouse.has_keys());
+        assert!(!mouse.is_gamepad());
+        assert_eq!(
+            ReportDescriptor::parse(&BOOT_KEYBOARD[..9]),
+            Err(DescriptorError::Truncated)
+        );
+        assert_eq!(
+            ReportDescriptor::parse(&[0xB4, 0x00]),
+            Err(DescriptorError::BadPushPop)
+        );
+    }
+
+    #[test]
+    fn test_limits() {
+        // keys A, B, ... in one bit each, listed by one Usage each
+        let keys = |inputs: u8, usages: u8| {
+            let mut descriptor = Vec::from([
+                0x05, 0x01, 0x09, 0x06, 0xA1, 0x01, 0x05, 0x07, 0x15, 0x00, 0x25, 0x01, 0x75, 0x01,
+                0x95, usages,
+            ]);
+            for input in 0..inputs {
+                for usage in 0..usages {
+                    descriptor.extend([0x09, 0x04 + input * usages + usage]);
+                }
+                descriptor.extend([0x81, 0x02]);
+            }
+            descriptor.push(0xC0);
+            descriptor
+        };
+
+        // the usages after the 16th are dropped, the last kept one covers the remaining
+        // elements, in order
+        let descriptor = ReportDescriptor::parse(&keys(1, 17)).unwrap();
+        assert!(descriptor.has_keys());
+        assert_eq!(
+            descriptor.decode(&[0x01, 0x00, 0x01]),
+            Some(report(0x00, [0x04, 0x14, 0, 0, 0, 0]))
+        );
+
+        // 16 fields fit, the 17th doesn't
+        assert!(ReportDescriptor::parse(&keys(16, 1)).unwrap().has_keys());
+        assert_eq!(
+            ReportDescriptor::parse(&keys(17, 1)),
+            Err(DescriptorError::TooManyFields)
+        );
+    }
+
+    #[test]
+    fn test_gamepad() {
+        use GamepadControl::*;
+        let descriptor = ReportDescriptor::parse(GAMEPAD).unwrap();
+        assert!(descriptor.is_gamepad());
+        assert!(!descriptor.has_keys());
+        assert!(!ReportDescriptor::parse(BOOT_KEYBOARD).unwrap().is_gamepad());
+
+        let held = |controls: &[GamepadControl]| {
+            let mut held = GamepadControls::default();
+            for &control in controls {
+                held.hold(control);
+            }
+            Some(held)
+        };
+        // stick centered, hat in the null state, nothing pressed
+        let idle = [0x80, 0x7F, 0x80, 0x80, 0x80, 0x0F, 0x00, 0x00];
+        assert_eq!(descriptor.decode_gamepad(&idle), held(&[]));
+        // stick pushed to the left, a bit down, button 1 and button 6
+        let bytes = [0x00, 0xA0, 0x80, 0x80, 0x80, 0x1F, 0x02, 0x00];
+        assert_eq!(
+            descriptor.decode_gamepad(&bytes),
+            held(&[Left, Button(1), Button(6)])
+        );
+        // hat south-west, stick fully up, button 12
+        let bytes = [0x80, 0x00, 0x80, 0x80, 0x80, 0x05, 0x80, 0x00];
+        assert_eq!(
+            descriptor.decode_gamepad(&bytes),
+            held(&[Up, Down, Left, Button(12)])
+        );
+        // the keys of a keyboard aren't gamepad controls
+        let keyboard = ReportDescriptor::parse(BOOT_KEYBOARD).unwrap();
+        assert_eq!(keyboard.decode_gamepad(&[0, 0, 0x5D, 0, 0, 0, 0, 0]), None);
+    }
+}
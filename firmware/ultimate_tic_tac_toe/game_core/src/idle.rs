@@ -0,0 +1,137 @@
+//! Idle mode: without any input, the matrix first dims and then shows an attract animation.
+//!
+//! The game itself keeps running underneath, so a key press returns to the exact same board.
+
+use rgb::RGB8;
+
+use crate::{
+    Frame,
+    rendering::{add_scaled, render_boot_animation},
+};
+
+/// brightness of the game while dimmed, relative to full brightness
+const DIM_BRIGHTNESS: f32 = 0.2;
+/// brightness of the attract animation, the boot animation at full brightness would dazzle
+const ATTRACT_BRIGHTNESS: f32 = 0.3;
+/// duration of the fade into the next phase, in seconds
+const FADE_TIME: f32 = 1.0;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum IdlePhase {
+    /// somebody is playing
+    Active,
+    /// no input for a while, the board is dimmed
+    Dimmed,
+    /// no input for a long time, the attract animation runs instead of the board
+    Attract,
+}
+
+/// Seconds without input until the matrix dims, and until the attract animation starts.
+/// None disables the step.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct IdleTimeouts {
+    pub dim_after: Option<u32>,
+    pub attract_after: Option<u32>,
+}
+
+impl Default for IdleTimeouts {
+    fn default() -> Self {
+        IdleTimeouts {
+            dim_after: Some(60),
+            attract_after: Some(300),
+        }
+    }
+}
+
+impl IdleTimeouts {
+    /// The phase after `idle_seconds` without input
+    pub fn phase(&self, idle_seconds: u32) -> IdlePhase {
+        let reached = |timeout: Option<u32>| timeout.is_some_and(|t| idle_seconds >= t);
+        if reached(self.attract_after) {
+            IdlePhase::Attract
+        } else if reached(self.dim_after) {
+            IdlePhase::Dimmed
+        } else {
+            IdlePhase::Active
+        }
+    }
+
+    /// The number of idle seconds at which the phase changes next, None if it doesn't change
+    /// anymore
+    pub fn next_change(&self, idle_seconds: u32) -> Option<u32> {
+        [self.dim_after, self.attract_after]
+            .into_iter()
+            .flatten()
+            .filter(|&t| t > idle_seconds)
+            .min()
+    }
+}
+
+/// Brightness (0..1) of the game in `phase`, `phase_elapsed` seconds after the phase started
+pub fn game_brightness(phase: IdlePhase, phase_elapsed: f32) -> f32 {
+    let fade = (phase_elapsed / FADE_TIME).clamp(0.0, 1.0);
+    match phase {
+        IdlePhase::Active => 1.0,
+        IdlePhase::Dimmed => 1.0 - (1.0 - DIM_BRIGHTNESS) * fade,
+        IdlePhase::Attract => DIM_BRIGHTNESS * (1.0 - fade),
+    }
+}
+
+/// Scale all LEDs by `brightness` (0..1)
+pub fn dim(leds: &mut [RGB8], brightness: f32) {
+    for led in leds {
+        let color = *led;
+        *led = RGB8::default();
+        add_scaled(led, color, brightness);
+    }
+}
+
+/// The frame to show instead of `game` in `phase`, `phase_elapsed` seconds after it started
+pub fn render_idle(game: &Frame, phase: IdlePhase, phase_elapsed: f32) -> Frame {
+    let mut frame = *game;
+    dim(&mut frame, game_brightness(phase, phase_elapsed));
+
+    if phase == IdlePhase::Attract {
+        // the rainbow waves fade in while the board fades out
+        let waves = render_boot_animation(phase_elapsed);
+        let amount = ATTRACT_BRIGHTNESS * (phase_elapsed / FADE_TIME).clamp(0.0, 1.0);
+        for (pixel, wave) in frame.iter_mut().zip(waves) {
+            add_scaled(pixel, wave, amount);
+        }
+    }
+    frame
+}
+
+#[cfg(test)]
+mod test_idle {
+    use super::{IdlePhase, IdleTimeouts};
+
+    #[test]
+    fn test_phases() {
+        let timeouts = IdleTimeouts {
+            dim_after: Some(10),
+            attract_after: Some(30),
+        };
+        assert_eq!(timeouts.phase(0), IdlePhase::Active);
+        assert_eq!(timeouts.phase(10), IdlePhase::Dimmed);
+        assert_eq!(timeouts.phase(30), IdlePhase::Attract);
+        assert_eq!(timeouts.next_change(0), Some(10));
+        assert_eq!(timeouts.next_change(10), Some(30));
+        assert_eq!(timeouts.next_change(30), None);
+
+        // without dimming, the attract animation starts directly
+        let timeouts = IdleTimeouts {
+            dim_after: None,
+            ..timeouts
+        };
+        assert_eq!(timeouts.phase(20), IdlePhase::Active);
+        assert_eq!(timeouts.next_change(0), Some(30));
+
+        let disabled = IdleTimeouts {
+            dim_after: None,
+            attract_after: None,
+        };
+        assert_eq!(disabled.phase(u32::MAX), IdlePhase::Active);
+        assert_eq!(disabled.next_change(0), None);
+    }
+}
@@ -0,0 +1,337 @@
+//! Auto-repeat and debounce for key press events, like the keyboard driver of a PC does.
+//!
+//! Holding a repeating key (the arrow keys) produces additional presses after a delay and then
+//! at a fixed interval. A key which is pressed again right after it was released is treated as
+//! chatter: the press is suppressed and the key counts as held all along.
+//! Every keyboard has its own state, as if it was plugged into a PC of its own.
+//!
+//! Times are in milliseconds from an arbitrary start, so the timing can be tested without a
+//! real clock.
+
+/// Timing of the auto-repeat and debounce, in milliseconds
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RepeatSettings {
+    /// from the press to the first repeat, None disables the auto-repeat
+    pub delay: Option<u32>,
+    /// between two repeats
+    pub interval: u32,
+    /// a press of the same key within this time after its release is suppressed
+    pub debounce: u32,
+}
+
+impl Default for RepeatSettings {
+    fn default() -> Self {
+        // the cursor only has three cells per row, a PC-like 30 Hz would overshoot all the time
+        RepeatSettings {
+            delay: Some(400),
+            interval: 150,
+            debounce: 20,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct HeldKey {
+    usage: u8,
+    /// None for a key which doesn't repeat
+    next_repeat: Option<u64>,
+}
+
+/// Turns the press and release events of one keyboard into the presses passed on to the game.
+/// Several keyboards need one each, see `KeyRepeats`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct KeyRepeat {
+    settings: RepeatSettings,
+    /// the key pressed last, only this one repeats
+    held: Option<HeldKey>,
+    /// the key released last and when, to detect chatter
+    released: Option<(HeldKey, u64)>,
+}
+
+impl KeyRepeat {
+    pub fn new(settings: RepeatSettings) -> Self {
+        KeyRepeat {
+            settings,
+            held: None,
+            released: None,
+        }
+    }
+
+    /// A key was pressed at `now`. Returns false if the press is a bounce or a duplicate and
+    /// must be ignored. `repeats` tells whether holding this key repeats it.
+    pub fn press(&mut self, usage: u8, repeats: bool, now: u64) -> bool {
+        if self.held.is_some_and(|held| held.usage == usage) {
+            // a duplicate report of a key which is already held
+            return false;
+        }
+        let debounce = self.settings.debounce as u64;
+        let bounce = self
+            .released
+            .filter(|&(released, at)| released.usage == usage && now.saturating_sub(at) < debounce);
+        if let Some((released, _)) = bounce {
+            // chatter, the key was held all along
+            self.held = Some(released);
+            self.released = None;
+            return false;
+        }
+
+        let next_repeat = match self.settings.delay {
+            Some(delay) if repeats => Some(now + delay as u64),
+            _ => None,
+        };
+        self.held = Some(HeldKey { usage, next_repeat });
+        true
+    }
+
+    /// A key was released at `now`
+    pub fn release(&mut self, usage: u8, now: u64) {
+        if let Some(held) = self.held.filter(|held| held.usage == usage) {
+            self.held = None;
+            self.released = Some((held, now));
+            return;
+        }
+        // another key was released, e.g. the first one while rolling over to the next
+        self.released = Some((
+            HeldKey {
+                usage,
+                next_repeat: None,
+            },
+            now,
+        ));
+    }
+
+    /// When `poll` has to be called next, None while no key repeats
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.held.and_then(|held| held.next_repeat)
+    }
+
+    /// The key to repeat if its time has come at `now`, together with the time at which it is
+    /// due. Call it until it returns None, in case several repeats are overdue.
+    pub fn poll(&mut self, now: u64) -> Option<(u8, u64)> {
+        let held = self.held.as_mut()?;
+        let due = held.next_repeat.filter(|&due| due <= now)?;
+        held.next_repeat = Some(due + self.settings.interval.max(1) as u64);
+        Some((held.usage, due))
+    }
+}
+
+/// A `KeyRepeat` for each of up to `N` keyboards, by an ID like the USB address. Like on two
+/// PCs, the keyboards don't affect each other: a press of the key the other keyboard holds
+/// isn't a duplicate or a bounce, and its release doesn't stop the other repeat.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct KeyRepeats<const N: usize> {
+    settings: RepeatSettings,
+    keyboards: [Option<(u8, KeyRepeat)>; N],
+}
+
+impl<const N: usize> KeyRepeats<N> {
+    pub fn new(settings: RepeatSettings) -> Self {
+        KeyRepeats {
+            settings,
+            keyboards: [None; N],
+        }
+    }
+
+    fn get_mut(&mut self, keyboard: u8) -> Option<&mut KeyRepeat> {
+        self.keyboards
+            .iter_mut()
+            .flatten()
+            .find(|(id, _)| *id == keyboard)
+            .map(|(_, repeat)| repeat)
+    }
+
+    /// A key of `keyboard` was pressed, see `KeyRepeat::press`. The keys of keyboards beyond
+    /// the first `N` neither repeat nor get debounced.
+    pub fn press(&mut self, keyboard: u8, usage: u8, repeats: bool, now: u64) -> bool {
+        if self.get_mut(keyboard).is_none() {
+            let settings = self.settings;
+            let Some(slot) = self.keyboards.iter_mut().find(|slot| slot.is_none()) else {
+                return true;
+            };
+            *slot = Some((keyboard, KeyRepeat::new(settings)));
+        }
+        match self.get_mut(keyboard) {
+            Some(repeat) => repeat.press(usage, repeats, now),
+            None => true,
+        }
+    }
+
+    /// A key of `keyboard` was released at `now`
+    pub fn release(&mut self, keyboard: u8, usage: u8, now: u64) {
+        if let Some(repeat) = self.get_mut(keyboard) {
+            repeat.release(usage, now);
+        }
+    }
+
+    /// Forget `keyboard`, e.g. when it was unplugged and the releases of its keys never arrive.
+    /// The next keyboard with this ID starts with nothing held.
+    pub fn remove(&mut self, keyboard: u8) {
+        for slot in &mut self.keyboards {
+            if slot.is_some_and(|(id, _)| id == keyboard) {
+                *slot = None;
+            }
+        }
+    }
+
+    /// When `poll` has to be called next, None while no key repeats
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.keyboards
+            .iter()
+            .flatten()
+            .filter_map(|(_, repeat)| repeat.next_deadline())
+            .min()
+    }
+
+    /// The keyboard and key to repeat if its time has come at `now`, with the time at which it
+    /// is due, the earliest one first. Call it until it returns None.
+    pub fn poll(&mut self, now: u64) -> Option<(u8, u8, u64)> {
+        let (keyboard, repeat) = self
+            .keyboards
+            .iter_mut()
+            .flatten()
+            .filter(|(_, repeat)| repeat.next_deadline().is_some_and(|due| due <= now))
+            .min_by_key(|(_, repeat)| repeat.next_deadline())?;
+        let (usage, due) = repeat.poll(now)?;
+        Some((*keyboard, usage, due))
+    }
+}
+
+#[cfg(test)]
+mod test_key_repeat {
+    extern crate std;
+    use std::vec::Vec;
+
+    use super::{KeyRepeat, KeyRepeats, RepeatSettings};
+
+    const UP: u8 = 0x52;
+    const ENTER: u8 = 0x28;
+
+    /// Poll every millisecond like a mock clock, returns the times of the repeats
+    fn repeats_until(repeat: &mut KeyRepeat, from: u64, until: u64) -> Vec<u64> {
+        let mut times = Vec::new();
+        for now in from..=until {
+            while let Some((_, due)) = repeat.poll(now) {
+                assert_eq!(due, now);
+                times.push(now);
+            }
+        }
+        times
+    }
+
+    #[test]
+    fn test_auto_repeat() {
+        let mut repeat = KeyRepeat::new(RepeatSettings {
+            delay: Some(400),
+            interval: 100,
+            debounce: 20,
+        });
+        assert!(repeat.press(UP, true, 1000));
+        assert_eq!(repeat.next_deadline(), Some(1400));
+        assert_eq!(
+            repeats_until(&mut repeat, 1000, 1750),
+            [1400, 1500, 1600, 1700]
+        );
+        repeat.release(UP, 1750);
+        assert_eq!(repeat.next_deadline(), None);
+        assert!(repeats_until(&mut repeat, 1750, 3000).is_empty());
+
+        // a key that doesn't repeat
+        assert!(repeat.press(ENTER, false, 3000));
+        assert!(repeats_until(&mut repeat, 3000, 5000).is_empty());
+
+        // overdue repeats, e.g. after the task was busy for a while
+        assert!(repeat.press(UP, true, 6000));
+        assert_eq!(repeat.poll(6650), Some((UP, 6400)));
+        assert_eq!(repeat.poll(6650), Some((UP, 6500)));
+        assert_eq!(repeat.poll(6650), Some((UP, 6600)));
+        assert_eq!(repeat.poll(6650), None);
+
+        // disabled
+        let mut repeat = KeyRepeat::new(RepeatSettings {
+            delay: None,
+            ..RepeatSettings::default()
+        });
+        assert!(repeat.press(UP, true, 0));
+        assert!(repeats_until(&mut repeat, 0, 5000).is_empty());
+    }
+
+    #[test]
+    fn test_debounce() {
+        let mut repeat = KeyRepeat::new(RepeatSettings {
+            delay: Some(400),
+            interval: 100,
+            debounce: 20,
+        });
+        assert!(repeat.press(UP, true, 0));
+        // a duplicate press without release
+        assert!(!repeat.press(UP, true, 5));
+        // contact bounce: the key stays held and keeps its repeat schedule
+        repeat.release(UP, 100);
+        assert!(!repeat.press(UP, true, 110));
+        assert_eq!(repeat.next_deadline(), Some(400));
+
+        // a real second press after the debounce time
+        repeat.release(UP, 500);
+        assert!(repeat.press(UP, true, 520));
+        assert_eq!(repeat.next_deadline(), Some(920));
+
+        // a different key right after a release isn't a bounce
+        repeat.release(UP, 600);
+        assert!(repeat.press(ENTER, false, 601));
+    }
+
+    #[test]
+    fn test_rollover() {
+        let mut repeat = KeyRepeat::new(RepeatSettings::default());
+        // the new key takes over the repeat, releasing the old one doesn't stop it
+        assert!(repeat.press(UP, true, 0));
+        assert!(repeat.press(ENTER, true, 100));
+        repeat.release(UP, 200);
+        assert_eq!(repeat.next_deadline(), Some(500));
+    }
+
+    #[test]
+    fn test_two_keyboards() {
+        let mut repeats = KeyRepeats::<2>::new(RepeatSettings {
+            delay: Some(400),
+            interval: 100,
+            debounce: 20,
+        });
+        // both players hold the same key, the second press isn't a duplicate
+        assert!(repeats.press(1, UP, true, 0));
+        assert!(repeats.press(2, UP, true, 50));
+        assert_eq!(repeats.poll(460), Some((1, UP, 400)));
+        assert_eq!(repeats.poll(460), Some((2, UP, 450)));
+        assert_eq!(repeats.poll(460), None);
+
+        // player two releasing it doesn't stop player one's repeat
+        repeats.release(2, UP, 470);
+        assert_eq!(repeats.next_deadline(), Some(500));
+        assert_eq!(repeats.poll(500), Some((1, UP, 500)));
+        assert_eq!(repeats.poll(700), Some((1, UP, 600)));
+
+        // a press right after the other keyboard's release isn't a bounce
+        repeats.release(1, UP, 1000);
+        assert!(repeats.press(2, UP, true, 1005));
+        // but on the same keyboard it is
+        assert!(!repeats.press(1, UP, true, 1010));
+
+        // a third keyboard doesn't fit, its keys are passed on without repeat
+        assert!(repeats.press(3, UP, true, 1020));
+        assert!(repeats.press(3, UP, true, 1021));
+    }
+
+    #[test]
+    fn test_unplugged() {
+        let mut repeats = KeyRepeats::<2>::new(RepeatSettings::default());
+        // the keyboard is unplugged while the key is held, its release never arrives
+        assert!(repeats.press(1, UP, true, 0));
+        assert_eq!(repeats.poll(500), Some((1, UP, 400)));
+        repeats.remove(1);
+        assert_eq!(repeats.next_deadline(), None);
+        assert_eq!(repeats.poll(5000), None);
+        // the key on the next keyboard with this address counts as a new press
+        assert!(repeats.press(1, UP, true, 5001));
+        assert_eq!(repeats.next_deadline(), Some(5401));
+    }
+}
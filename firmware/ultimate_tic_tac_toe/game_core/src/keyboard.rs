@@ -0,0 +1,199 @@
+//! Turns HID boot keyboard reports into discrete key press and release events.
+//!
+//! A boot keyboard report only contains the keys held right now, so a held key shows up in
+//! every report. Diffing consecutive reports gives one press when a key goes down and one
+//! release when it goes up, independent of which of the six slots it is reported in.
+
+/// Usage of the first modifier (left control), the 8 modifier bits map to 0xE0..=0xE7
+pub const FIRST_MODIFIER_USAGE: u8 = 0xE0;
+
+/// Usages in the key slots which report an error instead of keys: "ErrorRollOver" when too
+/// many keys are held, "POSTFail" and "ErrorUndefined"
+const ERROR_USAGES: [u8; 3] = [0x01, 0x02, 0x03];
+
+/// A HID boot keyboard report, same layout as `hid_keyboard_report_t` in tinyusb
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct KeyboardReport {
+    /// one bit per modifier key, left control is bit 0
+    pub modifier: u8,
+    /// usages of the held keys, 0 for an empty slot
+    pub keycode: [u8; 6],
+}
+
+impl KeyboardReport {
+    /// Parse the 8 bytes of a boot keyboard report, None if it is too short
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let bytes: &[u8; 8] = bytes.get(..8)?.try_into().ok()?;
+        // bytes[1] is reserved
+        Some(KeyboardReport {
+            modifier: bytes[0],
+            keycode: [bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7]],
+        })
+    }
+
+    /// True if the keyboard reports an error (e.g. too many keys held) instead of its keys
+    pub fn is_error(&self) -> bool {
+        self.keycode
+            .iter()
+            .any(|usage| ERROR_USAGES.contains(usage))
+    }
+}
+
+/// A change of a key, identified by its HID usage (page 0x07)
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum KeyEvent {
+    Pressed(u8),
+    Released(u8),
+}
+
+/// The keys held on one keyboard, updated by each report
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct KeyboardState {
+    modifier: u8,
+    keys: [u8; 6],
+}
+
+impl KeyboardState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// True if the key with `usage` is held, modifiers included
+    pub fn is_pressed(&self, usage: u8) -> bool {
+        match usage.checked_sub(FIRST_MODIFIER_USAGE) {
+            Some(bit) if bit < 8 => self.modifier & (1 << bit) != 0,
+            _ => usage != 0 && self.keys.contains(&usage),
+        }
+    }
+
+    /// Apply the next report and call `on_event` for every key which changed.
+    /// Releases come before presses, so rolling from one key to the next releases the first.
+    ///
+    /// In an error report (rollover or phantom state) the key slots are not valid, the held
+    /// keys stay as they were until the next valid report. The modifiers are still valid.
+    pub fn update(&mut self, report: &KeyboardReport, mut on_event: impl FnMut(KeyEvent)) {
+        let changed = self.modifier ^ report.modifier;
+        for bit in (0..8).filter(|bit| changed & (1 << bit) != 0) {
+            if report.modifier & (1 << bit) == 0 {
+                on_event(KeyEvent::Released(FIRST_MODIFIER_USAGE + bit));
+            }
+        }
+
+        let keys = if report.is_error() {
+            self.keys
+        } else {
+            report.keycode
+        };
+        for (i, &usage) in self.keys.iter().enumerate() {
+            if usage != 0 && !keys.contains(&usage) && !self.keys[..i].contains(&usage) {
+                on_event(KeyEvent::Released(usage));
+            }
+        }
+        for bit in (0..8).filter(|bit| changed & (1 << bit) != 0) {
+            if report.modifier & (1 << bit) != 0 {
+                on_event(KeyEvent::Pressed(FIRST_MODIFIER_USAGE + bit));
+            }
+        }
+        for (i, &usage) in keys.iter().enumerate() {
+            // a key reported in two slots is only pressed once
+            if usage != 0 && !self.keys.contains(&usage) && !keys[..i].contains(&usage) {
+                on_event(KeyEvent::Pressed(usage));
+            }
+        }
+
+        self.modifier = report.modifier;
+        self.keys = keys;
+    }
+
+    /// Release everything, e.g. when the keyboard is unplugged
+    pub fn reset(&mut self, on_event: impl FnMut(KeyEvent)) {
+        self.update(&KeyboardReport::default(), on_event);
+    }
+}
+
+#[cfg(test)]
+mod test_keyboard {
+    extern crate std;
+    use std::vec::Vec;
+
+    use super::{KeyEvent, KeyboardReport, KeyboardState};
+    use KeyEvent::{Pressed, Released};
+
+    /// Feed the recorded raw reports and collect the events
+    fn events(state: &mut KeyboardState, reports: &[[u8; 8]]) -> Vec<KeyEvent> {
+        let mut events = Vec::new();
+        for bytes in reports {
+            let report = KeyboardReport::from_bytes(bytes).unwrap();
+            state.update(&report, |event| events.push(event));
+        }
+        events
+    }
+
+    #[test]
+    fn test_held_key_and_rollover() {
+        let mut state = KeyboardState::new();
+        // numpad 5 held for three reports, then numpad 6 pressed while 5 is still held
+        let reports = [
+            [0, 0, 0x5D, 0, 0, 0, 0, 0],
+            [0, 0, 0x5D, 0, 0, 0, 0, 0],
+            [0, 0, 0x5D, 0, 0, 0, 0, 0],
+            [0, 0, 0x5D, 0x5E, 0, 0, 0, 0],
+            // 5 released, 6 moves to the first slot
+            [0, 0, 0x5E, 0, 0, 0, 0, 0],
+            [0, 0, 0, 0, 0, 0, 0, 0],
+        ];
+        assert_eq!(
+            events(&mut state, &reports),
+            [Pressed(0x5D), Pressed(0x5E), Released(0x5D), Released(0x5E)]
+        );
+        assert_eq!(state, KeyboardState::new());
+    }
+
+    #[test]
+    fn test_modifiers() {
+        let mut state = KeyboardState::new();
+        // left shift, then shift + 1, then right alt instead of left shift
+        let reports = [
+            [0x02, 0, 0, 0, 0, 0, 0, 0],
+            [0x02, 0, 0x1E, 0, 0, 0, 0, 0],
+            [0x40, 0, 0x1E, 0, 0, 0, 0, 0],
+        ];
+        assert_eq!(
+            events(&mut state, &reports),
+            [Pressed(0xE1), Pressed(0x1E), Released(0xE1), Pressed(0xE6)]
+        );
+        assert!(state.is_pressed(0xE6));
+        assert!(!state.is_pressed(0xE1));
+        assert!(state.is_pressed(0x1E));
+
+        let mut released = Vec::new();
+        state.reset(|event| released.push(event));
+        assert_eq!(released, [Released(0xE6), Released(0x1E)]);
+    }
+
+    #[test]
+    fn test_error_reports() {
+        let mut state = KeyboardState::new();
+        // too many keys: the keys stay held, the modifier change still counts
+        let reports = [
+            [0, 0, 0x04, 0x05, 0, 0, 0, 0],
+            [0x01, 0, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01],
+            [0x01, 0, 0x04, 0, 0, 0, 0, 0],
+        ];
+        assert_eq!(
+            events(&mut state, &reports),
+            [Pressed(0x04), Pressed(0x05), Pressed(0xE0), Released(0x05)]
+        );
+
+        // a key in two slots and a too short report
+        let mut state = KeyboardState::new();
+        assert_eq!(
+            events(
+                &mut state,
+                &[[0, 0, 0x28, 0x28, 0, 0, 0, 0], [0, 0, 0, 0, 0, 0, 0, 0]]
+            ),
+            [Pressed(0x28), Released(0x28)]
+        );
+        assert_eq!(KeyboardReport::from_bytes(&[0, 0, 0x28]), None);
+    }
+}
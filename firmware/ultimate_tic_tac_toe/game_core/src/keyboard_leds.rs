@@ -0,0 +1,70 @@
+//! The lock LEDs of the keyboards, a second indicator of the game right where the players'
+//! hands are. The value is the one byte LED output report of a boot keyboard.
+
+use crate::game::{GameStage, Player};
+
+pub const NUM_LOCK: u8 = 0x01;
+pub const CAPS_LOCK: u8 = 0x02;
+pub const SCROLL_LOCK: u8 = 0x04;
+pub const ALL_LEDS: u8 = NUM_LOCK | CAPS_LOCK | SCROLL_LOCK;
+
+/// blinks per second
+const BLINK_FREQUENCY: f32 = 1.0;
+
+/// The LEDs for `game_stage`, `elapsed` is the time in seconds since it changed.
+///
+/// - player one's turn (or color choice): Num Lock on
+/// - player two's turn: Num Lock blinking
+/// - illegal move: Caps Lock on as well
+/// - won: all LEDs on
+/// - draw: Scroll Lock on
+pub fn render_keyboard_leds(game_stage: &GameStage, elapsed: f32) -> u8 {
+    let cycles = elapsed * BLINK_FREQUENCY;
+    let blink_on = cycles - libm::floorf(cycles) < 0.5;
+    let turn = |player: Player| match player {
+        Player::PlayerOne => NUM_LOCK,
+        Player::PlayerTwo if blink_on => NUM_LOCK,
+        Player::PlayerTwo => 0,
+    };
+    match game_stage {
+        GameStage::ChooseColor(player) => turn(*player),
+        GameStage::InProgress(state, _) => turn(state.current_player),
+        GameStage::IllegalMove(state, _, _) => turn(state.current_player) | CAPS_LOCK,
+        GameStage::Won(_, _) => ALL_LEDS,
+        GameStage::Draw(_) => SCROLL_LOCK,
+    }
+}
+
+#[cfg(test)]
+mod test_keyboard_leds {
+    use super::{ALL_LEDS, CAPS_LOCK, NUM_LOCK, render_keyboard_leds};
+    use crate::game::{
+        BoardState, GameStage, Move, NextUserSelection,
+        Player::{PlayerOne, PlayerTwo},
+    };
+
+    #[test]
+    fn test_turns() {
+        let mut state = BoardState::new();
+        let selection = NextUserSelection::SelectGrid;
+        assert_eq!(
+            render_keyboard_leds(&GameStage::InProgress(state, selection), 0.7),
+            NUM_LOCK
+        );
+        state.current_player = PlayerTwo;
+        let stage = GameStage::InProgress(state, selection);
+        assert_eq!(render_keyboard_leds(&stage, 0.2), NUM_LOCK);
+        assert_eq!(render_keyboard_leds(&stage, 0.7), 0);
+        assert_eq!(
+            render_keyboard_leds(
+                &GameStage::IllegalMove(state, selection, Move { grid: 1, cell: 1 }),
+                0.7
+            ),
+            CAPS_LOCK
+        );
+        assert_eq!(
+            render_keyboard_leds(&GameStage::Won(PlayerOne, state), 0.7),
+            ALL_LEDS
+        );
+    }
+}
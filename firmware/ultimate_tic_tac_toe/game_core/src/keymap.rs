@@ -0,0 +1,108 @@
+//! Which key does what: a table from keys, optionally combined with modifiers like Ctrl+N, to
+//! the inputs of the game. The table itself is configured in the firmware (`KEYMAP` in main.rs).
+
+use crate::keyboard::FIRST_MODIFIER_USAGE;
+
+/// Modifier keys, without telling the left and the right one apart
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Modifiers(u8);
+
+impl Modifiers {
+    pub const NONE: Self = Self(0);
+    pub const CTRL: Self = Self(0x01);
+    pub const SHIFT: Self = Self(0x02);
+    pub const ALT: Self = Self(0x04);
+    pub const GUI: Self = Self(0x08);
+
+    /// From the modifier byte of a keyboard report, the left keys are bits 0..3 and the right
+    /// keys bits 4..7
+    pub const fn from_report(modifier: u8) -> Self {
+        Self((modifier | modifier >> 4) & 0x0F)
+    }
+
+    /// Both sets held together, e.g. `Modifiers::CTRL.with(Modifiers::SHIFT)`
+    pub const fn with(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+/// Bit of the modifier byte of a keyboard report for the key with `usage`, 0 for other keys
+pub fn modifier_bit(usage: u8) -> u8 {
+    match usage.checked_sub(FIRST_MODIFIER_USAGE) {
+        Some(bit) if bit < 8 => 1 << bit,
+        _ => 0,
+    }
+}
+
+/// A key by its HID usage, with the modifiers which have to be held
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct KeyBinding {
+    pub usage: u8,
+    pub modifiers: Modifiers,
+}
+
+impl KeyBinding {
+    /// The key on its own
+    pub const fn key(usage: u8) -> Self {
+        Self {
+            usage,
+            modifiers: Modifiers::NONE,
+        }
+    }
+
+    /// The key while Ctrl is held
+    pub const fn ctrl(usage: u8) -> Self {
+        Self {
+            usage,
+            modifiers: Modifiers::CTRL,
+        }
+    }
+}
+
+/// The input bound to the key with `usage` while the modifiers in `modifier` (modifier byte
+/// of the report) are held. The modifiers have to match exactly, so Ctrl+N doesn't trigger
+/// what N alone does.
+pub fn lookup<T: Copy>(keymap: &[(KeyBinding, T)], usage: u8, modifier: u8) -> Option<T> {
+    let pressed = KeyBinding {
+        usage,
+        modifiers: Modifiers::from_report(modifier),
+    };
+    keymap
+        .iter()
+        .find(|(binding, _)| *binding == pressed)
+        .map(|&(_, input)| input)
+}
+
+#[cfg(test)]
+mod test_keymap {
+    use super::{KeyBinding, Modifiers, lookup, modifier_bit};
+
+    const KEYMAP: &[(KeyBinding, char)] = &[
+        (KeyBinding::key(0x11), 'n'),
+        (KeyBinding::ctrl(0x11), 'N'),
+        (
+            KeyBinding {
+                usage: 0x1D,
+                modifiers: Modifiers::CTRL.with(Modifiers::SHIFT),
+            },
+            'Z',
+        ),
+    ];
+
+    #[test]
+    fn test_lookup() {
+        assert_eq!(lookup(KEYMAP, 0x11, 0), Some('n'));
+        // left and right control
+        assert_eq!(lookup(KEYMAP, 0x11, 0x01), Some('N'));
+        assert_eq!(lookup(KEYMAP, 0x11, 0x10), Some('N'));
+        // an extra modifier doesn't match
+        assert_eq!(lookup(KEYMAP, 0x11, 0x01 | 0x04), None);
+        // left control and right shift
+        assert_eq!(lookup(KEYMAP, 0x1D, 0x01 | 0x20), Some('Z'));
+        assert_eq!(lookup(KEYMAP, 0x1D, 0), None);
+
+        assert_eq!(modifier_bit(0xE0), 0x01);
+        assert_eq!(modifier_bit(0xE5), 0x20);
+        assert_eq!(modifier_bit(0x11), 0);
+    }
+}
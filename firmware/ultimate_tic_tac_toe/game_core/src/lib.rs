@@ -0,0 +1,37 @@
+//! Hardware independent part of the game: the game state and its rendering onto the LED matrix.
+//!
+//! This crate doesn't depend on the MCU, so the rendering can also be run and tested on the host.
+#![no_std]
+
+pub mod game;
+pub mod gamepad;
+pub mod hid_descriptor;
+pub mod idle;
+pub mod key_repeat;
+pub mod keyboard;
+pub mod keyboard_leds;
+pub mod keymap;
+pub mod pairing;
+pub mod rendering;
+pub mod samples;
+pub mod settings;
+pub mod status_led;
+pub mod status_text;
+pub mod text;
+pub mod theme;
+pub mod triple_buffer;
+pub mod ws2812;
+pub mod zoom;
+
+use rgb::RGB8;
+
+pub const MATRIX_WIDTH: usize = 16; // 3x3 grid plus borders
+pub const MATRIX_HEIGHT: usize = 16; // 3x3 grid plus borders
+pub const MATRIX_LENGTH: usize = MATRIX_WIDTH * MATRIX_HEIGHT;
+
+/// One frame for the LED matrix, in the order of the LED strip (see `rendering::xy`)
+pub type Frame = [RGB8; MATRIX_LENGTH];
+
+/// LEDs of the optional status strip next to the matrix
+pub const STATUS_STRIP_LENGTH: usize = 8;
+pub type StatusStrip = [RGB8; STATUS_STRIP_LENGTH];
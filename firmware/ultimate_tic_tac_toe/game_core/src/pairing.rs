@@ -0,0 +1,179 @@
+//! Which keyboard belongs to which player, for two keyboards connected through a USB hub.
+//!
+//! Each player's keyboard is bound while they choose their color at the start of a game,
+//! then only that keyboard can make their moves. With a single keyboard, both players share it.
+//! A gamepad counts as a keyboard here.
+//!
+//! A keyboard gets a new USB address when it is plugged in again, so a binding whose keyboard
+//! was removed moves to the next keyboard with the same vendor and product ID.
+
+use crate::game::Player;
+
+/// USB devices connected at the same time, the hub included
+const MAX_CONNECTED: usize = 8;
+
+/// A keyboard, by its USB address and vendor/product ID
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct KeyboardId {
+    pub dev_addr: u8,
+    pub vid: u16,
+    pub pid: u16,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Binding {
+    keyboard: KeyboardId,
+    /// false while the keyboard is unplugged
+    connected: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Keyboards {
+    connected: [Option<KeyboardId>; MAX_CONNECTED],
+    /// per player
+    bindings: [Option<Binding>; 2],
+}
+
+impl Keyboards {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A keyboard was plugged in
+    pub fn mounted(&mut self, keyboard: KeyboardId) {
+        self.unmounted(keyboard.dev_addr);
+        if let Some(slot) = self.connected.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(keyboard);
+        }
+
+        // the first player whose keyboard of this kind was unplugged gets it back
+        let same_kind = |binding: &&mut Binding| {
+            !binding.connected
+                && binding.keyboard.vid == keyboard.vid
+                && binding.keyboard.pid == keyboard.pid
+        };
+        if let Some(binding) = self.bindings.iter_mut().flatten().find(same_kind) {
+            *binding = Binding {
+                keyboard,
+                connected: true,
+            };
+        }
+    }
+
+    /// The device with `dev_addr` was removed, it doesn't have to be a keyboard
+    pub fn unmounted(&mut self, dev_addr: u8) {
+        for slot in &mut self.connected {
+            if slot.is_some_and(|keyboard| keyboard.dev_addr == dev_addr) {
+                *slot = None;
+            }
+        }
+        for binding in self.bindings.iter_mut().flatten() {
+            if binding.keyboard.dev_addr == dev_addr {
+                binding.connected = false;
+            }
+        }
+    }
+
+    pub fn any_connected(&self) -> bool {
+        self.connected.iter().any(Option::is_some)
+    }
+
+    fn binding(&self, player: Player) -> Option<Binding> {
+        self.bindings[player as usize - 1]
+    }
+
+    /// True if the keyboard with `dev_addr` may enter the keys of `player`
+    pub fn may_play(&self, player: Player, dev_addr: u8) -> bool {
+        match self.binding(player) {
+            // an unplugged keyboard doesn't lock the player out
+            Some(binding) => !binding.connected || binding.keyboard.dev_addr == dev_addr,
+            None => match self.binding(player.other()) {
+                // the other player's keyboard, unless it's the only one
+                Some(other) if other.connected && other.keyboard.dev_addr == dev_addr => {
+                    self.connected.iter().flatten().count() <= 1
+                }
+                _ => true,
+            },
+        }
+    }
+
+    /// Bind the keyboard with `dev_addr` to `player`
+    pub fn bind(&mut self, player: Player, dev_addr: u8) {
+        let keyboard = self
+            .connected
+            .iter()
+            .flatten()
+            .find(|keyboard| keyboard.dev_addr == dev_addr);
+        self.bindings[player as usize - 1] = keyboard.map(|&keyboard| Binding {
+            keyboard,
+            connected: true,
+        });
+    }
+
+    /// Forget which keyboard belongs to whom, before the next game
+    pub fn unbind_all(&mut self) {
+        self.bindings = [None; 2];
+    }
+}
+
+#[cfg(test)]
+mod test_pairing {
+    use super::{KeyboardId, Keyboards};
+    use crate::game::Player::{PlayerOne, PlayerTwo};
+
+    const A: KeyboardId = KeyboardId {
+        dev_addr: 2,
+        vid: 0x046D,
+        pid: 0xC31C,
+    };
+    const B: KeyboardId = KeyboardId {
+        dev_addr: 3,
+        vid: 0x04D9,
+        pid: 0x1702,
+    };
+
+    #[test]
+    fn test_two_keyboards() {
+        let mut keyboards = Keyboards::new();
+        keyboards.mounted(A);
+        keyboards.mounted(B);
+
+        // player one pairs keyboard A, then player two can't use it
+        assert!(keyboards.may_play(PlayerOne, A.dev_addr));
+        keyboards.bind(PlayerOne, A.dev_addr);
+        assert!(!keyboards.may_play(PlayerTwo, A.dev_addr));
+        assert!(keyboards.may_play(PlayerTwo, B.dev_addr));
+        keyboards.bind(PlayerTwo, B.dev_addr);
+        assert!(!keyboards.may_play(PlayerOne, B.dev_addr));
+
+        // A is plugged in again and gets a new address
+        keyboards.unmounted(A.dev_addr);
+        // meanwhile nobody is locked out
+        assert!(keyboards.may_play(PlayerOne, B.dev_addr));
+        let replugged = KeyboardId { dev_addr: 4, ..A };
+        keyboards.mounted(replugged);
+        assert!(keyboards.may_play(PlayerOne, 4));
+        assert!(!keyboards.may_play(PlayerOne, B.dev_addr));
+        assert!(!keyboards.may_play(PlayerTwo, 4));
+
+        keyboards.unbind_all();
+        assert!(keyboards.may_play(PlayerTwo, 4));
+    }
+
+    #[test]
+    fn test_shared_keyboard() {
+        let mut keyboards = Keyboards::new();
+        assert!(!keyboards.any_connected());
+        keyboards.mounted(A);
+        assert!(keyboards.any_connected());
+        keyboards.bind(PlayerOne, A.dev_addr);
+        // the only keyboard is shared
+        assert!(keyboards.may_play(PlayerTwo, A.dev_addr));
+        keyboards.bind(PlayerTwo, A.dev_addr);
+        assert!(keyboards.may_play(PlayerOne, A.dev_addr));
+        assert!(keyboards.may_play(PlayerTwo, A.dev_addr));
+
+        keyboards.unmounted(A.dev_addr);
+        assert!(!keyboards.any_connected());
+    }
+}
@@ -0,0 +1,588 @@
+use rgb::RGB8;
+
+use crate::{
+    Frame, MATRIX_HEIGHT, MATRIX_LENGTH, MATRIX_WIDTH, STATUS_STRIP_LENGTH, StatusStrip,
+    game::{BoardState, GameStage, Player},
+    text::FONT_5X7,
+    theme::{ColorSettings, PLAYER_COLOR_CHOICES, PlayerCoding},
+};
+
+/// Convert from x,y coordinates to the linear NeoPixel index
+/// The XY coordinates are 0-indexed, with (0,0) at the top-left
+/// x goes right, y goes down
+// is_multiple_of is too new for the esp toolchain
+#[allow(clippy::manual_is_multiple_of)]
+pub fn xy<T>(arr: &mut [T], x: usize, y: usize) -> &mut T {
+    // the strip starts at top left, goes down, then one right and up, one right and down, ...
+    // so even columns go down, odd columns go up.
+    let index = if x % 2 == 0 {
+        // Even columns go down
+        (x * MATRIX_WIDTH) + y
+    } else {
+        // Odd columns go up
+        (x * MATRIX_WIDTH) + (MATRIX_WIDTH - 1 - y)
+    };
+    &mut arr[index]
+}
+
+/// lit cells of a won mini-grid with PlayerCoding::Shape, row-major
+const SHAPE_X: [bool; 9] = [true, false, true, false, true, false, true, false, true];
+const SHAPE_O: [bool; 9] = [true, true, true, true, false, true, true, true, true];
+
+/// lowest brightness of the pulsing cells of player two with PlayerCoding::Blink
+const BLINK_MIN_BRIGHTNESS: f32 = 0.3;
+
+/// brightness of the focused cell, relative to the color of the current player
+pub(crate) const FOCUS_BRIGHTNESS: f32 = 0.5;
+/// brightness of the frame around the mini-grid a move would send the opponent to,
+/// relative to the color of the opponent
+pub(crate) const TARGET_FRAME_BRIGHTNESS: f32 = 0.25;
+
+/// how much the last moves dim during their blink, the most recent one first
+const LAST_MOVE_BLINK_DEPTH: [f32; 2] = [0.6, 0.3];
+
+/// duration of one sweep along a winning line, in seconds
+const WIN_SWEEP_PERIOD: f32 = 1.5;
+
+/// Brightness (0..1) of element `index` (0..3) of a winning line at time `elapsed` (seconds).
+/// A triangular peak travels along the line, then pauses for a moment before it repeats.
+fn win_sweep(elapsed: f32, index: usize) -> f32 {
+    // the peak travels from -0.5 to 4.5, so it fully enters and leaves the line
+    let cycles = elapsed / WIN_SWEEP_PERIOD;
+    let position = (cycles - libm::floorf(cycles)) * 5.0 - 0.5;
+    let distance = libm::fabsf(position - index as f32);
+    (1.0 - distance).max(0.0)
+}
+
+/// how long the final board is shown before the result scrolls over it, in seconds
+const RESULT_BOARD_TIME: f32 = 4.0;
+/// scroll speed of text, in pixels per second
+const TEXT_SCROLL_SPEED: f32 = 12.0;
+
+/// After a game, alternate between showing the final board and scrolling `text` over it
+fn overlay_result_text(colors: &mut [RGB8], text: &str, color: RGB8, elapsed: f32) {
+    let scroll_time = FONT_5X7.scroll_duration(text, TEXT_SCROLL_SPEED);
+    let t = elapsed % (RESULT_BOARD_TIME + scroll_time);
+    if t < RESULT_BOARD_TIME {
+        return;
+    }
+
+    // black band behind the text, one pixel larger than the font
+    let top = (MATRIX_HEIGHT - FONT_5X7.height) / 2;
+    for y in top - 1..top + FONT_5X7.height + 1 {
+        for x in 0..MATRIX_WIDTH {
+            *xy(colors, x, y) = RGB8::default();
+        }
+    }
+    FONT_5X7.draw_scrolling(
+        colors,
+        text,
+        top as i32,
+        t - RESULT_BOARD_TIME,
+        TEXT_SCROLL_SPEED,
+        color,
+    );
+}
+
+/// Brightness (0..1) of the cells of `player`.
+/// With PlayerCoding::Blink, the cells of player two pulse at 0.5 Hz.
+pub(crate) fn cell_brightness(color_settings: &ColorSettings, player: Player, elapsed: f32) -> f32 {
+    if player == Player::PlayerTwo && color_settings.coding == PlayerCoding::Blink {
+        let env = (1.0 + libm::cosf(core::f32::consts::PI * elapsed)) * 0.5;
+        BLINK_MIN_BRIGHTNESS + (1.0 - BLINK_MIN_BRIGHTNESS) * env
+    } else {
+        1.0
+    }
+}
+
+/// Brightness (0..1) of an occupied cell, including the blink which marks the last moves.
+/// The blink starts at full brightness and dims once per second.
+pub(crate) fn occupied_cell_brightness(
+    color_settings: &ColorSettings,
+    board_state: &BoardState,
+    i_grid: usize,
+    i_cell: usize,
+    elapsed: f32,
+) -> f32 {
+    let player_brightness = match board_state.board[i_grid][i_cell] {
+        Some(player) => cell_brightness(color_settings, player, elapsed),
+        None => return 0.0,
+    };
+    let blink = match board_state.move_age(i_grid, i_cell) {
+        Some(age) if age < color_settings.marked_moves as usize => {
+            let env = (1.0 - libm::cosf(2.0 * core::f32::consts::PI * elapsed)) * 0.5;
+            1.0 - LAST_MOVE_BLINK_DEPTH[age] * env
+        }
+        _ => 1.0,
+    };
+    player_brightness * blink
+}
+
+/// add `amount` (0..1) of `color` onto `pixel`
+pub(crate) fn add_scaled(pixel: &mut RGB8, color: RGB8, amount: f32) {
+    *pixel = RGB8::new(
+        pixel.r.saturating_add((color.r as f32 * amount) as u8),
+        pixel.g.saturating_add((color.g as f32 * amount) as u8),
+        pixel.b.saturating_add((color.b as f32 * amount) as u8),
+    );
+}
+
+/// The glow of the empty cells the current player can choose from, pulsing at 1 Hz
+pub(crate) fn glow_pulse(color_settings: &ColorSettings, elapsed: f32) -> RGB8 {
+    let glow = color_settings.theme().current_grid_glow;
+    let omega = 2.0 * core::f32::consts::PI * 1.0; // 1 Hz pulse
+    let env = (1.0 + libm::cosf(omega * elapsed)) * 0.5;
+    RGB8::new(
+        (glow.r as f32 * env) as u8,
+        (glow.g as f32 * env) as u8,
+        (glow.b as f32 * env) as u8,
+    )
+}
+
+/// Render the animation shown while booting, `elapsed` is in seconds since boot
+pub fn render_boot_animation(elapsed: f32) -> Frame {
+    // Demo: Three sine waves cycling through the 16x16 matrix
+    // Red starts at 0, Blue at 1/3, Green at 2/3 of the cycle
+    let mut colors = [RGB8::new(0, 0, 0); MATRIX_LENGTH];
+
+    let time_offset = elapsed * 6.0; // Animation speed
+
+    for (led_index, color) in colors.iter_mut().enumerate() {
+        let position = (led_index as f32) / 256.0 * 2.0 * core::f32::consts::PI;
+
+        // Three sine waves offset by 2π/3 (120 degrees)
+        let red_phase = position + time_offset;
+        let blue_phase = position + time_offset + 2.0 * core::f32::consts::PI / 3.0;
+        let green_phase = position + time_offset + 4.0 * core::f32::consts::PI / 3.0;
+
+        // Calculate sine values and convert to 0-255 range
+        let red = ((libm::sinf(red_phase)) * 255.0) as u8;
+        let green = ((libm::sinf(green_phase)) * 255.0) as u8;
+        let blue = ((libm::sinf(blue_phase)) * 255.0) as u8;
+
+        *color = RGB8::new(red, green, blue);
+    }
+
+    colors
+}
+
+/// Keyboard with its cable, shown while no keyboard is connected.
+/// '#' is the case, 'k' a key, 'c' the cable and 'p' the plug.
+const KEYBOARD_ICON: [&str; MATRIX_HEIGHT] = [
+    "................",
+    ".......pp.......",
+    ".......pp.......",
+    "........c.......",
+    "........c.......",
+    "........c.......",
+    ".##############.",
+    ".#............#.",
+    ".#.k.k.k.k.k..#.",
+    ".#............#.",
+    ".#..k.k.k.k.k.#.",
+    ".#............#.",
+    ".#...kkkkkk...#.",
+    ".#............#.",
+    ".##############.",
+    "................",
+];
+
+/// Render the keyboard icon, the plug blinks to ask for a keyboard.
+/// `elapsed` is the time in seconds since the keyboard was unplugged.
+pub fn render_keyboard_icon(elapsed: f32) -> Frame {
+    let mut colors = [RGB8::default(); MATRIX_LENGTH];
+    let plug_env = (1.0 + libm::cosf(2.0 * core::f32::consts::PI * elapsed)) * 0.5;
+    let mut plug = RGB8::default();
+    add_scaled(&mut plug, RGB8::new(40, 40, 40), plug_env);
+
+    for (y, row) in KEYBOARD_ICON.iter().enumerate() {
+        for (x, c) in row.chars().enumerate() {
+            *xy(&mut colors, x, y) = match c {
+                '#' => RGB8::new(30, 30, 30),
+                'k' => RGB8::new(12, 12, 12),
+                'c' => RGB8::new(8, 8, 8),
+                'p' => plug,
+                _ => continue,
+            };
+        }
+    }
+    colors
+}
+
+/// Render one frame of the game, `stage_elapsed` is the time in seconds since `game_stage` changed.
+/// This is a pure function, so it can be used on the host to preview the rendering.
+pub fn render_frame(
+    game_stage: &GameStage,
+    color_settings: &ColorSettings,
+    stage_elapsed: f32,
+) -> Frame {
+    let game_stage = *game_stage;
+    let theme = color_settings.theme();
+
+    let board_state = match &game_stage {
+        GameStage::InProgress(state, _)
+        | GameStage::Won(_, state)
+        | GameStage::Draw(state)
+        | GameStage::IllegalMove(state, _, _) => *state,
+        GameStage::ChooseColor(_) => BoardState::new(),
+    };
+
+    let mut colors = [RGB8::new(0, 0, 0); MATRIX_LENGTH];
+
+    // render board state
+
+    // 0 based indizes
+    let cell_offset = |board_idx: usize, cell_idx: usize| -> (usize, usize) {
+        let board_column = board_idx % 3;
+        let board_row = board_idx / 3;
+
+        let x_grid = 1 + board_column * 5;
+        let y_grid = 1 + board_row * 5;
+
+        let cell_x = cell_idx % 3;
+        let cell_y = cell_idx / 3;
+
+        let x = x_grid + cell_x;
+        let y = y_grid + cell_y;
+        (x, y)
+    };
+
+    // Compute selection glow and selection state
+    let selection = match game_stage {
+        GameStage::InProgress(_, sel) => Some(sel),
+        GameStage::IllegalMove(_, sel, _) => Some(sel),
+        _ => None,
+    };
+
+    // Draw occupied cells (one pixel per cell)
+    for i_board in 0..9 {
+        for i_cell in 0..9 {
+            if let Some(player) = board_state.board[i_board][i_cell] {
+                let (x, y) = cell_offset(i_board, i_cell);
+
+                add_scaled(
+                    xy(&mut colors, x, y),
+                    color_settings.player_color(player),
+                    occupied_cell_brightness(
+                        color_settings,
+                        &board_state,
+                        i_board,
+                        i_cell,
+                        stage_elapsed,
+                    ),
+                );
+            }
+        }
+    }
+
+    let board_glow_amount: u8 = 10;
+    for i_board in 0..9 {
+        if let Some(finished) = board_state.finished_grids[i_board] {
+            // compute winner color or white for draw
+            let winner_color = match finished {
+                crate::game::PlayerOrDraw::Player(p) => color_settings.player_color(p),
+                crate::game::PlayerOrDraw::Draw => theme.draw_color,
+            };
+
+            let glow_color = RGB8::new(
+                (winner_color.r as u16 * board_glow_amount as u16 / 255) as u8,
+                (winner_color.g as u16 * board_glow_amount as u16 / 255) as u8,
+                (winner_color.b as u16 * board_glow_amount as u16 / 255) as u8,
+            );
+
+            for i_cell in 0..9 {
+                if board_state.board[i_board][i_cell].is_none() {
+                    let (x, y) = cell_offset(i_board, i_cell);
+
+                    let pixel = xy(&mut colors, x, y);
+                    // add glow by mixing a small amount of glow_color into the pixel
+                    *pixel = glow_color;
+                }
+            }
+
+            let (left, top) = cell_offset(i_board, 0);
+            let (right, bottom) = cell_offset(i_board, 8);
+
+            for x in left - 1..right + 2 {
+                *xy(&mut colors, x, top - 1) = glow_color;
+                *xy(&mut colors, x, bottom + 1) = glow_color;
+            }
+
+            for y in top - 1..bottom + 2 {
+                *xy(&mut colors, left - 1, y) = glow_color;
+                *xy(&mut colors, right + 1, y) = glow_color;
+            }
+
+            // with PlayerCoding::Shape, the mini-grid is replaced by a large X or O
+            let shape = match finished {
+                crate::game::PlayerOrDraw::Player(Player::PlayerOne) => Some(SHAPE_X),
+                crate::game::PlayerOrDraw::Player(Player::PlayerTwo) => Some(SHAPE_O),
+                crate::game::PlayerOrDraw::Draw => None,
+            };
+            if let (Some(shape), PlayerCoding::Shape) = (shape, color_settings.coding) {
+                for (i_cell, &lit) in shape.iter().enumerate() {
+                    let (x, y) = cell_offset(i_board, i_cell);
+                    *xy(&mut colors, x, y) = if lit { winner_color } else { RGB8::default() };
+                }
+            }
+
+            // sweep along the three cells which won this mini-grid
+            if let Some(line) = board_state.grid_winning_line(i_board) {
+                for (i, &i_cell) in line.iter().enumerate() {
+                    let (x, y) = cell_offset(i_board, i_cell);
+                    add_scaled(
+                        xy(&mut colors, x, y),
+                        winner_color,
+                        win_sweep(stage_elapsed, i),
+                    );
+                }
+            }
+        }
+    }
+
+    let glow_pulse = glow_pulse(color_settings, stage_elapsed);
+
+    // Apply selection glow: if SelectGrid => all empty cells glow; if SelectCell(grid)
+    // => only empty cells inside that big-grid glow. Do not glow border pixels.
+    if let Some(sel) = selection {
+        match sel {
+            crate::game::NextUserSelection::SelectGrid => {
+                for i_board in 0..9 {
+                    if board_state.finished_grids[i_board].is_some() {
+                        // skip finished big-grids
+                        continue;
+                    }
+                    for i_cell in 0..9 {
+                        if board_state.board[i_board][i_cell].is_none() {
+                            let (x, y) = cell_offset(i_board, i_cell);
+
+                            let pixel = xy(&mut colors, x, y);
+                            *pixel = RGB8::new(
+                                pixel.r.saturating_add(glow_pulse.r),
+                                pixel.g.saturating_add(glow_pulse.g),
+                                pixel.b.saturating_add(glow_pulse.b),
+                            );
+                        }
+                    }
+                }
+            }
+            crate::game::NextUserSelection::SelectCell(grid, focus) => {
+                let i_grid: usize = (grid - 1) as usize;
+                for i_cell in 0..9 {
+                    if board_state.board[i_grid][i_cell].is_none() {
+                        let (x, y) = cell_offset(i_grid, i_cell);
+
+                        let pixel = xy(&mut colors, x, y);
+                        *pixel = RGB8::new(
+                            pixel.r.saturating_add(glow_pulse.r),
+                            pixel.g.saturating_add(glow_pulse.g),
+                            pixel.b.saturating_add(glow_pulse.b),
+                        );
+                    }
+                }
+
+                // preview where the focused cell would send the opponent: a frame around the
+                // target mini-grid, or around all open mini-grids if it is already decided
+                let focus =
+                    focus.filter(|&cell| board_state.board[i_grid][cell as usize - 1].is_none());
+                if let Some(cell) = focus {
+                    let player = board_state.current_player;
+                    let (x, y) = cell_offset(i_grid, cell as usize - 1);
+                    let pixel = xy(&mut colors, x, y);
+                    *pixel = RGB8::default();
+                    add_scaled(pixel, color_settings.player_color(player), FOCUS_BRIGHTNESS);
+
+                    let mut frame_color = RGB8::default();
+                    add_scaled(
+                        &mut frame_color,
+                        color_settings.player_color(player.other()),
+                        TARGET_FRAME_BRIGHTNESS,
+                    );
+
+                    let targets: [bool; 9] = match board_state.move_target(grid, cell) {
+                        Some(target) => core::array::from_fn(|i| i == target as usize - 1),
+                        None => board_state.open_grids_after_move(grid, cell),
+                    };
+                    for i_board in (0..9).filter(|&i| targets[i]) {
+                        let (left, top) = cell_offset(i_board, 0);
+                        let (right, bottom) = cell_offset(i_board, 8);
+                        for x in left - 1..right + 2 {
+                            *xy(&mut colors, x, top - 1) = frame_color;
+                            *xy(&mut colors, x, bottom + 1) = frame_color;
+                        }
+                        for y in top - 1..bottom + 2 {
+                            *xy(&mut colors, left - 1, y) = frame_color;
+                            *xy(&mut colors, right + 1, y) = frame_color;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    if let GameStage::IllegalMove(_, _, played_move) = game_stage {
+        let (x, y) = cell_offset(
+            (played_move.grid - 1) as usize,
+            (played_move.cell - 1) as usize,
+        );
+        // compute a pulsing glow effect
+        let pixel = xy(&mut colors, x, y);
+        let omega = 2.0 * core::f32::consts::PI * 2.0;
+        let env = (1.0 + libm::cosf(omega * stage_elapsed)) * 0.5;
+        let blend_channel = |a: u8, b: u8, env: f32| -> u8 {
+            let af = (a as f32) * env;
+            let bf = (b as f32) * (1.0 - env);
+            let sum = af + bf;
+            if sum >= 255.0 { 255u8 } else { sum as u8 }
+        };
+        let new_r = blend_channel(theme.error_glow.r, pixel.r, env);
+        let new_g = blend_channel(theme.error_glow.g, pixel.g, env);
+        let new_b = blend_channel(theme.error_glow.b, pixel.b, env);
+
+        *pixel = RGB8::new(new_r, new_g, new_b);
+    }
+
+    match game_stage {
+        GameStage::Won(winner, _) => {
+            // flash the winner's color on the border
+            let border_color = color_settings.player_color(winner);
+            for x in 0..16 {
+                *xy(&mut colors, x, 0) = border_color;
+                *xy(&mut colors, x, 15) = border_color;
+            }
+            for y in 0..16 {
+                *xy(&mut colors, 0, y) = border_color;
+                *xy(&mut colors, 15, y) = border_color;
+            }
+
+            // sweep along the three mini-grids which won the game, including their borders
+            if let Some(line) = board_state.winning_line() {
+                for (i, &i_board) in line.iter().enumerate() {
+                    let amount = win_sweep(stage_elapsed, i) * 0.5;
+                    let (left, top) = cell_offset(i_board, 0);
+                    let (right, bottom) = cell_offset(i_board, 8);
+                    for x in left - 1..right + 2 {
+                        for y in top - 1..bottom + 2 {
+                            add_scaled(xy(&mut colors, x, y), border_color, amount);
+                        }
+                    }
+                }
+            }
+
+            let text = match winner {
+                Player::PlayerOne => "P1 WINS",
+                Player::PlayerTwo => "P2 WINS",
+            };
+            overlay_result_text(&mut colors, text, border_color, stage_elapsed);
+        }
+        GameStage::Draw(_) => {
+            // gray border for draw
+            let border_color = RGB8::new(50, 50, 50);
+            for y in [0, 15] {
+                for x in 0..16 {
+                    *xy(&mut colors, x, y) = border_color;
+                }
+            }
+
+            overlay_result_text(&mut colors, "DRAW", border_color, stage_elapsed);
+        }
+        GameStage::IllegalMove(_, _, _) | GameStage::InProgress(_, _) => {
+            // highlight current player
+            let player_color = color_settings.player_color(board_state.current_player);
+            if board_state.current_player == Player::PlayerOne {
+                for x in 0..MATRIX_WIDTH {
+                    *xy(&mut colors, x, 0) = player_color;
+                }
+            } else {
+                for x in 0..MATRIX_WIDTH {
+                    *xy(&mut colors, x, 15) = player_color;
+                }
+            }
+        }
+        GameStage::ChooseColor(player) => {
+            // each mini-grid shows one of the colors to choose from
+            let own = color_settings.player_colors[player as usize - 1] as usize;
+            let other = color_settings.player_colors[2 - player as usize] as usize;
+            for (i_board, &choice) in PLAYER_COLOR_CHOICES.iter().enumerate() {
+                // the color of the other player can't be chosen, so it's dimmed
+                let amount = if i_board == other { 0.2 } else { 1.0 };
+                for i_cell in 0..9 {
+                    let (x, y) = cell_offset(i_board, i_cell);
+                    add_scaled(xy(&mut colors, x, y), choice, amount);
+                }
+            }
+
+            // frame the currently chosen color
+            let (left, top) = cell_offset(own, 0);
+            let (right, bottom) = cell_offset(own, 8);
+            let frame_brightness =
+                (40.0 * (1.0 + libm::cosf(2.0 * core::f32::consts::PI * stage_elapsed))) as u8;
+            let frame_color = RGB8::new(frame_brightness, frame_brightness, frame_brightness);
+            for x in left - 1..right + 2 {
+                *xy(&mut colors, x, top - 1) = frame_color;
+                *xy(&mut colors, x, bottom + 1) = frame_color;
+            }
+            for y in top - 1..bottom + 2 {
+                *xy(&mut colors, left - 1, y) = frame_color;
+                *xy(&mut colors, right + 1, y) = frame_color;
+            }
+
+            // show who is choosing at the same place as the current player during the game
+            let y = match player {
+                Player::PlayerOne => 0,
+                Player::PlayerTwo => 15,
+            };
+            for x in 0..MATRIX_WIDTH {
+                *xy(&mut colors, x, y) = PLAYER_COLOR_CHOICES[own];
+            }
+        }
+    }
+
+    colors
+}
+
+/// Render the status strip next to the matrix: the color of the player whose turn it is,
+/// the winner (with a sweep running along the strip) or gray for a draw.
+pub fn render_status_strip(
+    game_stage: &GameStage,
+    color_settings: &ColorSettings,
+    stage_elapsed: f32,
+) -> StatusStrip {
+    let mut strip = [RGB8::default(); STATUS_STRIP_LENGTH];
+    match *game_stage {
+        GameStage::ChooseColor(player) => strip.fill(color_settings.player_color(player)),
+        GameStage::InProgress(state, _) | GameStage::IllegalMove(state, _, _) => {
+            strip.fill(color_settings.player_color(state.current_player))
+        }
+        GameStage::Won(winner, _) => {
+            let color = color_settings.player_color(winner);
+            for (i, led) in strip.iter_mut().enumerate() {
+                // the same sweep as over the winning line, with the strip split into three parts
+                *led = color;
+                add_scaled(
+                    led,
+                    color,
+                    win_sweep(stage_elapsed, i * 3 / STATUS_STRIP_LENGTH),
+                );
+            }
+        }
+        GameStage::Draw(_) => strip.fill(RGB8::new(50, 50, 50)),
+    }
+    strip
+}
+
+#[cfg(test)]
+mod test_xy {
+    use super::xy;
+
+    #[test]
+    fn test_xy_function() {
+        // each element holds its own index
+        let mut arr: [usize; 256] = core::array::from_fn(|i| i);
+        assert_eq!(*xy(&mut arr, 0, 0), 0);
+        assert_eq!(*xy(&mut arr, 0, 1), 1);
+        assert_eq!(*xy(&mut arr, 0, 15), 15);
+        assert_eq!(*xy(&mut arr, 1, 15), 16);
+        assert_eq!(*xy(&mut arr, 1, 0), 31);
+    }
+}
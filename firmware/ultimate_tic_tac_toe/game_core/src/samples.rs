@@ -0,0 +1,171 @@
+//! Fixed game stages, for previewing the rendering on the host and for tests.
+
+use crate::game::{
+    BoardState, GameStage, Move, NextUserSelection, Player, PlayerOrDraw, find_line,
+};
+
+/// Build a board from 9 rows of 9 characters: 'X' is player one, 'O' player two, anything else
+/// is empty. Spaces are ignored, so the mini-grids can be separated for readability.
+/// The finished mini-grids are derived from the cells.
+pub fn board_from_rows(rows: [&str; 9], current_player: Player) -> BoardState {
+    let mut board = BoardState::new();
+    board.current_player = current_player;
+
+    for (row, text) in rows.iter().enumerate() {
+        for (col, c) in text.chars().filter(|c| *c != ' ').take(9).enumerate() {
+            let i_grid = (row / 3) * 3 + col / 3;
+            let i_cell = (row % 3) * 3 + col % 3;
+            board.board[i_grid][i_cell] = match c {
+                'X' => Some(Player::PlayerOne),
+                'O' => Some(Player::PlayerTwo),
+                _ => None,
+            };
+        }
+    }
+
+    for i_grid in 0..9 {
+        let cells = &board.board[i_grid];
+        board.finished_grids[i_grid] = if find_line(cells, Player::PlayerOne).is_some() {
+            Some(PlayerOrDraw::Player(Player::PlayerOne))
+        } else if find_line(cells, Player::PlayerTwo).is_some() {
+            Some(PlayerOrDraw::Player(Player::PlayerTwo))
+        } else if cells.iter().all(|c| c.is_some()) {
+            Some(PlayerOrDraw::Draw)
+        } else {
+            None
+        };
+    }
+
+    board
+}
+
+/// A game in progress: player one won the top-left and bottom-center mini-grids,
+/// player two the bottom-right one, the center-right one is a draw.
+fn mid_game() -> BoardState {
+    let board = board_from_rows(
+        [
+            "X.O O.. ...",
+            "OX. .X. ...",
+            "..X ... O..",
+            "... .O. XOX",
+            ".X. ... XOO",
+            "... X.. OXX",
+            "O.. XXX .O.",
+            ".O. O.. .O.",
+            "... .O. .O.",
+        ],
+        Player::PlayerOne,
+    );
+    BoardState {
+        // player one sent player two to the center, who sent them to the top-center
+        last_moves: [
+            Some(Move { grid: 5, cell: 2 }),
+            Some(Move { grid: 2, cell: 5 }),
+        ],
+        ..board
+    }
+}
+
+/// Player one won with the diagonal from top-left to bottom-right
+fn won_game() -> BoardState {
+    let board = board_from_rows(
+        [
+            "XXX ... OOO",
+            "O.O .X. ...",
+            "... ... X..",
+            ".O. X.. ...",
+            "... .X. .O.",
+            "... O.X ...",
+            "... O.. ..X",
+            "X.. ... .X.",
+            "... ... X.O",
+        ],
+        Player::PlayerTwo,
+    );
+    BoardState {
+        // the winning move
+        last_moves: [Some(Move { grid: 9, cell: 5 }), None],
+        ..board
+    }
+}
+
+/// Every cell is filled, without a winner
+fn draw_game() -> BoardState {
+    board_from_rows(
+        [
+            "XXX OOO XXX",
+            "OOX XXO OOX",
+            "XOO OXX XOO",
+            "XXX OOO OOO",
+            "OOX XXO XXO",
+            "XOO OXX OXX",
+            "OOO XXX XXX",
+            "XXO OOX OOX",
+            "OXX XOO XOO",
+        ],
+        Player::PlayerOne,
+    )
+}
+
+/// All sample stages with their names
+pub fn all() -> [(&'static str, GameStage); 11] {
+    [
+        ("choose_color", GameStage::ChooseColor(Player::PlayerOne)),
+        (
+            "start",
+            GameStage::InProgress(BoardState::new(), NextUserSelection::SelectCell(1, None)),
+        ),
+        (
+            "select_grid",
+            GameStage::InProgress(mid_game(), NextUserSelection::SelectGrid),
+        ),
+        (
+            "select_cell",
+            GameStage::InProgress(mid_game(), NextUserSelection::SelectCell(2, None)),
+        ),
+        (
+            // the cursor is on a cell which sends the opponent to the top-right mini-grid
+            "focus",
+            GameStage::InProgress(mid_game(), NextUserSelection::SelectCell(2, Some(3))),
+        ),
+        (
+            // the bottom-center mini-grid is already won, so the opponent can choose freely
+            "focus_free_choice",
+            GameStage::InProgress(mid_game(), NextUserSelection::SelectCell(2, Some(8))),
+        ),
+        (
+            // in the top-right mini-grid, the center cell sends the opponent to the center
+            "focus_corner",
+            GameStage::InProgress(mid_game(), NextUserSelection::SelectCell(3, Some(5))),
+        ),
+        (
+            "illegal_move",
+            GameStage::IllegalMove(
+                mid_game(),
+                NextUserSelection::SelectCell(2, None),
+                // already taken by player two
+                Move { grid: 2, cell: 1 },
+            ),
+        ),
+        (
+            "player_two",
+            GameStage::InProgress(
+                BoardState {
+                    current_player: Player::PlayerTwo,
+                    ..mid_game()
+                },
+                NextUserSelection::SelectCell(5, None),
+            ),
+        ),
+        ("draw", GameStage::Draw(draw_game())),
+        ("won", GameStage::Won(Player::PlayerOne, won_game())),
+    ]
+}
+
+/// The sample stage with the given name
+pub fn by_name(name: &str) -> Option<GameStage> {
+    all()
+        .into_iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, stage)| stage)
+}
@@ -0,0 +1,108 @@
+//! The user settings which survive a reboot, and their record in flash.
+//!
+//! The firmware stores the record at the start of the 'nvs' partition. It doesn't use
+//! ESP-IDF, so the partition isn't used for anything else.
+//!
+//! Layout: magic (4 bytes), version (1 byte), payload, checksum (1 byte, xor of the payload)
+
+use crate::theme::{ColorSettings, PLAYER_COLOR_CHOICES, PlayerCoding, THEMES};
+
+const MAGIC: [u8; 4] = *b"UTTT";
+const VERSION: u8 = 3;
+const PAYLOAD_LEN: usize = 6;
+pub const RECORD_LEN: usize = MAGIC.len() + 1 + PAYLOAD_LEN + 1;
+
+/// All user settings which survive a reboot
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Settings {
+    pub colors: ColorSettings,
+    /// zoom into the mini-grid when selecting a cell
+    pub zoom: bool,
+}
+
+impl Settings {
+    /// The record to store in flash
+    pub fn to_bytes(&self) -> [u8; RECORD_LEN] {
+        let mut bytes = [0u8; RECORD_LEN];
+        bytes[..4].copy_from_slice(&MAGIC);
+        bytes[4] = VERSION;
+
+        let payload = [
+            self.colors.theme,
+            self.colors.player_colors[0],
+            self.colors.player_colors[1],
+            self.colors.coding as u8,
+            self.zoom as u8,
+            self.colors.marked_moves,
+        ];
+        bytes[5..5 + PAYLOAD_LEN].copy_from_slice(&payload);
+        bytes[RECORD_LEN - 1] = checksum(&payload);
+        bytes
+    }
+
+    /// Parse a record read from flash, None if it is erased, corrupted or from another version
+    pub fn from_bytes(bytes: &[u8; RECORD_LEN]) -> Option<Self> {
+        if bytes[..4] != MAGIC || bytes[4] != VERSION {
+            return None;
+        }
+        let payload = &bytes[5..5 + PAYLOAD_LEN];
+        if checksum(payload) != bytes[RECORD_LEN - 1] {
+            return None;
+        }
+
+        let [theme, color_1, color_2, coding, zoom, marked_moves] = [
+            payload[0], payload[1], payload[2], payload[3], payload[4], payload[5],
+        ];
+        if theme as usize >= THEMES.len()
+            || color_1 as usize >= PLAYER_COLOR_CHOICES.len()
+            || color_2 as usize >= PLAYER_COLOR_CHOICES.len()
+            || zoom > 1
+            || marked_moves > 2
+        {
+            return None;
+        }
+
+        Some(Settings {
+            colors: ColorSettings {
+                theme,
+                player_colors: [color_1, color_2],
+                coding: PlayerCoding::from_u8(coding)?,
+                marked_moves,
+            },
+            zoom: zoom == 1,
+        })
+    }
+}
+
+fn checksum(payload: &[u8]) -> u8 {
+    payload.iter().fold(0xA5, |acc, b| acc ^ b)
+}
+
+#[cfg(test)]
+mod test_settings {
+    use super::{RECORD_LEN, Settings};
+    use crate::theme::{ColorSettings, PlayerCoding};
+
+    #[test]
+    fn test_roundtrip() {
+        let settings = Settings {
+            colors: ColorSettings {
+                theme: 2,
+                player_colors: [5, 8],
+                coding: PlayerCoding::Shape,
+                marked_moves: 2,
+            },
+            zoom: true,
+        };
+        let bytes = settings.to_bytes();
+        assert_eq!(Settings::from_bytes(&bytes), Some(settings));
+
+        // erased flash
+        assert_eq!(Settings::from_bytes(&[0xFF; RECORD_LEN]), None);
+
+        // corrupted payload
+        let mut corrupted = bytes;
+        corrupted[6] ^= 1;
+        assert_eq!(Settings::from_bytes(&corrupted), None);
+    }
+}
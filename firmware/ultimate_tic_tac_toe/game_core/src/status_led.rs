@@ -0,0 +1,160 @@
+//! The single RGB LED on the dev board, which shows the state of the system at a glance.
+
+use rgb::RGB8;
+
+use crate::{
+    game::GameStage,
+    rendering::add_scaled,
+    theme::{ColorSettings, PLAYER_COLOR_CHOICES},
+};
+
+/// The onboard LED sits right next to the eye of whoever flashes the board, keep it dim
+const BRIGHTNESS: f32 = 0.3;
+
+const BOOTING_COLOR: RGB8 = RGB8::new(40, 40, 40);
+const USB_READY_COLOR: RGB8 = RGB8::new(0, 0, 80);
+const KEYBOARD_MOUNTED_COLOR: RGB8 = RGB8::new(0, 80, 0);
+const ERROR_COLOR: RGB8 = RGB8::new(80, 0, 0);
+
+/// how long the LED flashes after a keyboard was mounted, before it shows whose turn it is
+pub const KEYBOARD_MOUNTED_TIME: f32 = 1.5;
+/// how long the LED blinks red after an error which may go away, e.g. a failed USB transfer
+pub const ERROR_TIME: f32 = 5.0;
+
+/// State of the system, in order of priority: an error hides everything else
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SystemState {
+    /// something failed to start, or failed just now, see the log
+    Error,
+    /// before the game and the USB host are running
+    Booting,
+    /// the USB host runs, but no keyboard is connected
+    UsbReady,
+    /// a keyboard was connected just now
+    KeyboardMounted,
+    /// the game runs
+    Playing,
+}
+
+impl SystemState {
+    /// The state to show, `since_error` and `since_keyboard_mounted` are in seconds or None
+    /// without an error or keyboard. A fatal error is shown for good.
+    pub fn new(
+        fatal_error: bool,
+        since_error: Option<f32>,
+        usb_ready: bool,
+        since_keyboard_mounted: Option<f32>,
+    ) -> Self {
+        let error = fatal_error || since_error.is_some_and(|t| t < ERROR_TIME);
+        match since_keyboard_mounted {
+            _ if error => SystemState::Error,
+            Some(t) if t < KEYBOARD_MOUNTED_TIME => SystemState::KeyboardMounted,
+            Some(_) => SystemState::Playing,
+            None if usb_ready => SystemState::UsbReady,
+            None => SystemState::Booting,
+        }
+    }
+}
+
+/// Render the onboard LED, `elapsed` is the time in seconds since `state` or the game stage
+/// changed. `game_stage` is None until the game runs.
+///
+/// - error: fast red blink (4 Hz)
+/// - booting: white, slowly breathing
+/// - USB host ready: blue, blinking once per second while waiting for a keyboard
+/// - keyboard mounted: green double flash
+/// - playing: the color of the player whose turn it is, the winner blinks after the game
+pub fn render_status_led(
+    state: SystemState,
+    game_stage: Option<&GameStage>,
+    color_settings: &ColorSettings,
+    elapsed: f32,
+) -> RGB8 {
+    let on_off = |frequency: f32| {
+        let cycles = elapsed * frequency;
+        if cycles - libm::floorf(cycles) < 0.5 {
+            1.0
+        } else {
+            0.0
+        }
+    };
+    let (color, amount) = match state {
+        SystemState::Error => (ERROR_COLOR, on_off(4.0)),
+        SystemState::Booting => (
+            BOOTING_COLOR,
+            (1.0 - libm::cosf(core::f32::consts::PI * elapsed)) * 0.5,
+        ),
+        SystemState::UsbReady => (USB_READY_COLOR, on_off(1.0)),
+        // two flashes within the first 0.75 s
+        SystemState::KeyboardMounted => (
+            KEYBOARD_MOUNTED_COLOR,
+            if elapsed < 0.75 { on_off(4.0) } else { 0.0 },
+        ),
+        SystemState::Playing => match game_stage.copied() {
+            None => (RGB8::default(), 0.0),
+            Some(GameStage::ChooseColor(player)) => {
+                // the color the player is choosing right now
+                let own = color_settings.player_colors[player as usize - 1] as usize;
+                (PLAYER_COLOR_CHOICES[own % PLAYER_COLOR_CHOICES.len()], 1.0)
+            }
+            Some(GameStage::InProgress(state, _) | GameStage::IllegalMove(state, _, _)) => {
+                (color_settings.player_color(state.current_player), 1.0)
+            }
+            Some(GameStage::Won(winner, _)) => (color_settings.player_color(winner), on_off(1.0)),
+            Some(GameStage::Draw(_)) => (BOOTING_COLOR, 1.0),
+        },
+    };
+
+    let mut led = RGB8::default();
+    add_scaled(&mut led, color, amount * BRIGHTNESS);
+    led
+}
+
+#[cfg(test)]
+mod test_status_led {
+    use super::SystemState;
+
+    #[test]
+    fn test_system_state() {
+        assert_eq!(
+            SystemState::new(false, None, false, None),
+            SystemState::Booting
+        );
+        assert_eq!(
+            SystemState::new(false, None, true, None),
+            SystemState::UsbReady
+        );
+        assert_eq!(
+            SystemState::new(false, None, true, Some(0.5)),
+            SystemState::KeyboardMounted
+        );
+        assert_eq!(
+            SystemState::new(false, None, true, Some(5.0)),
+            SystemState::Playing
+        );
+        assert_eq!(
+            SystemState::new(true, None, true, Some(5.0)),
+            SystemState::Error
+        );
+        assert_eq!(
+            SystemState::new(true, Some(60.0), true, Some(5.0)),
+            SystemState::Error
+        );
+    }
+
+    #[test]
+    fn test_error_expires() {
+        assert_eq!(
+            SystemState::new(false, Some(1.0), true, Some(60.0)),
+            SystemState::Error
+        );
+        assert_eq!(
+            SystemState::new(false, Some(super::ERROR_TIME), true, Some(60.0)),
+            SystemState::Playing
+        );
+        assert_eq!(
+            SystemState::new(false, Some(60.0), true, None),
+            SystemState::UsbReady
+        );
+    }
+}
@@ -0,0 +1,143 @@
+//! Text for the optional OLED status display, with what the LED matrix can't show.
+
+use core::fmt::{self, Write};
+
+use crate::game::{BoardState, GameStage, Move, NextUserSelection, Player};
+
+/// mini-grids in row-major order
+const GRID_NAMES: [&str; 9] = [
+    "top-left",
+    "top",
+    "top-right",
+    "left",
+    "center",
+    "right",
+    "bottom-left",
+    "bottom",
+    "bottom-right",
+];
+
+fn player_name(player: Player) -> &'static str {
+    match player {
+        Player::PlayerOne => "P1 (X)",
+        Player::PlayerTwo => "P2 (O)",
+    }
+}
+
+fn player_symbol(player: Player) -> char {
+    match player {
+        Player::PlayerOne => 'X',
+        Player::PlayerTwo => 'O',
+    }
+}
+
+/// Why `attempt` wasn't allowed on `board_state`
+pub fn illegal_move_reason(board_state: &BoardState, attempt: Move) -> &'static str {
+    let i_grid = attempt.grid as usize - 1;
+    if board_state.finished_grids[i_grid].is_some() {
+        "grid decided"
+    } else if board_state.board[i_grid][attempt.cell as usize - 1].is_some() {
+        "cell taken"
+    } else {
+        "wrong grid"
+    }
+}
+
+/// Write the status as lines of at most 21 characters (128 pixels with a 6 pixel wide font).
+/// `game_seconds` is the time since the game started.
+pub fn write_status(
+    out: &mut impl Write,
+    game_stage: &GameStage,
+    game_seconds: u32,
+) -> fmt::Result {
+    let board_state = match *game_stage {
+        GameStage::ChooseColor(player) => {
+            return writeln!(out, "{} picks a color", player_name(player));
+        }
+        GameStage::InProgress(state, selection) | GameStage::IllegalMove(state, selection, _) => {
+            writeln!(out, "{} to move", player_name(state.current_player))?;
+            match selection {
+                NextUserSelection::SelectGrid => writeln!(out, "Any grid")?,
+                NextUserSelection::SelectCell(grid, _) => {
+                    writeln!(out, "Grid: {}", GRID_NAMES[grid as usize - 1])?
+                }
+            }
+            state
+        }
+        GameStage::Won(winner, state) => {
+            writeln!(out, "{} wins!", player_name(winner))?;
+            state
+        }
+        GameStage::Draw(state) => {
+            writeln!(out, "Draw")?;
+            state
+        }
+    };
+
+    let moves = board_state
+        .board
+        .iter()
+        .flatten()
+        .filter(|cell| cell.is_some())
+        .count();
+    writeln!(
+        out,
+        "Moves: {:<3}  {:>2}:{:02}",
+        moves,
+        game_seconds / 60,
+        game_seconds % 60
+    )?;
+
+    // oldest move first, so it reads in the order they were played
+    let mut last_moves = board_state.last_moves.iter().rev().flatten().peekable();
+    if last_moves.peek().is_some() {
+        write!(out, "Last:")?;
+        for m in last_moves {
+            let cell = board_state.board[m.grid as usize - 1][m.cell as usize - 1];
+            let symbol = cell.map(player_symbol).unwrap_or('?');
+            write!(out, " {}{}", symbol, m)?;
+        }
+        writeln!(out)?;
+    }
+
+    if let GameStage::IllegalMove(state, _, attempt) = *game_stage {
+        writeln!(out, "Illegal: {}", illegal_move_reason(&state, attempt))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test_status_text {
+    extern crate std;
+    use std::string::String;
+
+    use super::write_status;
+    use crate::samples;
+
+    fn status(sample: &str, game_seconds: u32) -> String {
+        let mut text = String::new();
+        write_status(&mut text, &samples::by_name(sample).unwrap(), game_seconds).unwrap();
+        text
+    }
+
+    #[test]
+    fn test_status() {
+        assert_eq!(status("choose_color", 0), "P1 (X) picks a color\n");
+        assert_eq!(
+            status("select_cell", 754),
+            "P1 (X) to move\nGrid: top\nMoves: 30   12:34\nLast: Xe8 Oe6\n"
+        );
+        assert_eq!(
+            status("illegal_move", 5),
+            "P1 (X) to move\nGrid: top\nMoves: 30    0:05\nLast: Xe8 Oe6\nIllegal: cell taken\n"
+        );
+        assert_eq!(
+            status("won", 61),
+            "P1 (X) wins!\nMoves: 22    1:01\nLast: Xh2\n"
+        );
+        // every line fits on the display
+        for (name, _) in samples::all() {
+            assert!(status(name, 6000).lines().all(|line| line.len() <= 21));
+        }
+    }
+}
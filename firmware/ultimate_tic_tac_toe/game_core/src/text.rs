@@ -0,0 +1,526 @@
+//! Bitmap fonts and a horizontal text scroller for the LED matrix.
+//!
+//! Only upper case letters are included, lower case letters are drawn as upper case.
+//! German umlauts and 'ß' are supported, since the project documentation is German.
+
+use rgb::RGB8;
+
+use crate::{MATRIX_HEIGHT, MATRIX_WIDTH, rendering::xy};
+
+pub struct Font {
+    pub width: usize,
+    pub height: usize,
+    /// each glyph is stored as rows from top to bottom,
+    /// the highest of the `width` bits is the leftmost pixel
+    glyphs: &'static [(char, &'static [u8])],
+}
+
+impl Font {
+    /// The rows of the glyph for `c`, '?' if the font doesn't contain it
+    fn glyph(&self, c: char) -> &'static [u8] {
+        let c = match c {
+            'ä' => 'Ä',
+            'ö' => 'Ö',
+            'ü' => 'Ü',
+            c => c.to_ascii_uppercase(),
+        };
+        self.glyphs
+            .iter()
+            .find(|(g, _)| *g == c)
+            .or_else(|| self.glyphs.iter().find(|(g, _)| *g == '?'))
+            .map(|(_, rows)| *rows)
+            .unwrap()
+    }
+
+    /// Width of `text` in pixels, with one pixel spacing between characters
+    pub fn text_width(&self, text: &str) -> usize {
+        let count = text.chars().count();
+        if count == 0 {
+            0
+        } else {
+            count * (self.width + 1) - 1
+        }
+    }
+
+    /// Draw `text` with its top-left corner at (x, y). Pixels outside the matrix are clipped,
+    /// only the set pixels of each glyph are drawn.
+    pub fn draw(&self, frame: &mut [RGB8], text: &str, x: i32, y: i32, color: RGB8) {
+        for (i_char, c) in text.chars().enumerate() {
+            let char_x = x + (i_char * (self.width + 1)) as i32;
+            if char_x >= MATRIX_WIDTH as i32 {
+                break;
+            }
+            if char_x + (self.width as i32) < 0 {
+                continue;
+            }
+
+            for (row, bits) in self.glyph(c).iter().enumerate() {
+                for column in 0..self.width {
+                    if bits & (1 << (self.width - 1 - column)) == 0 {
+                        continue;
+                    }
+                    let px = char_x + column as i32;
+                    let py = y + row as i32;
+                    if (0..MATRIX_WIDTH as i32).contains(&px)
+                        && (0..MATRIX_HEIGHT as i32).contains(&py)
+                    {
+                        *xy(frame, px as usize, py as usize) = color;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Time in seconds for `text` to scroll once completely through the matrix
+    pub fn scroll_duration(&self, text: &str, pixels_per_second: f32) -> f32 {
+        (self.text_width(text) + MATRIX_WIDTH) as f32 / pixels_per_second
+    }
+
+    /// Draw `text` scrolling from right to left at row `y`.
+    /// It enters at the right edge at `elapsed` = 0 and repeats after `scroll_duration`.
+    pub fn draw_scrolling(
+        &self,
+        frame: &mut [RGB8],
+        text: &str,
+        y: i32,
+        elapsed: f32,
+        pixels_per_second: f32,
+        color: RGB8,
+    ) {
+        let period = self.text_width(text) + MATRIX_WIDTH;
+        let offset = (elapsed * pixels_per_second) as usize % period;
+        self.draw(frame, text, MATRIX_WIDTH as i32 - offset as i32, y, color);
+    }
+}
+
+/// Small font, fits three lines on the matrix
+pub static FONT_3X5: Font = Font {
+    width: 3,
+    height: 5,
+    glyphs: &[
+        ('0', &[0b111, 0b101, 0b101, 0b101, 0b111]),
+        ('1', &[0b010, 0b110, 0b010, 0b010, 0b111]),
+        ('2', &[0b110, 0b001, 0b010, 0b100, 0b111]),
+        ('3', &[0b110, 0b001, 0b010, 0b001, 0b110]),
+        ('4', &[0b101, 0b101, 0b111, 0b001, 0b001]),
+        ('5', &[0b111, 0b100, 0b110, 0b001, 0b110]),
+        ('6', &[0b011, 0b100, 0b111, 0b101, 0b111]),
+        ('7', &[0b111, 0b001, 0b010, 0b010, 0b010]),
+        ('8', &[0b111, 0b101, 0b111, 0b101, 0b111]),
+        ('9', &[0b111, 0b101, 0b111, 0b001, 0b110]),
+        ('A', &[0b010, 0b101, 0b111, 0b101, 0b101]),
+        ('B', &[0b110, 0b101, 0b110, 0b101, 0b110]),
+        ('C', &[0b011, 0b100, 0b100, 0b100, 0b011]),
+        ('D', &[0b110, 0b101, 0b101, 0b101, 0b110]),
+        ('E', &[0b111, 0b100, 0b110, 0b100, 0b111]),
+        ('F', &[0b111, 0b100, 0b110, 0b100, 0b100]),
+        ('G', &[0b011, 0b100, 0b101, 0b101, 0b011]),
+        ('H', &[0b101, 0b101, 0b111, 0b101, 0b101]),
+        ('I', &[0b111, 0b010, 0b010, 0b010, 0b111]),
+        ('J', &[0b001, 0b001, 0b001, 0b101, 0b010]),
+        ('K', &[0b101, 0b101, 0b110, 0b101, 0b101]),
+        ('L', &[0b100, 0b100, 0b100, 0b100, 0b111]),
+        ('M', &[0b101, 0b111, 0b111, 0b101, 0b101]),
+        ('N', &[0b110, 0b101, 0b101, 0b101, 0b101]),
+        ('O', &[0b010, 0b101, 0b101, 0b101, 0b010]),
+        ('P', &[0b110, 0b101, 0b110, 0b100, 0b100]),
+        ('Q', &[0b010, 0b101, 0b101, 0b110, 0b011]),
+        ('R', &[0b110, 0b101, 0b110, 0b101, 0b101]),
+        ('S', &[0b011, 0b100, 0b010, 0b001, 0b110]),
+        ('T', &[0b111, 0b010, 0b010, 0b010, 0b010]),
+        ('U', &[0b101, 0b101, 0b101, 0b101, 0b111]),
+        ('V', &[0b101, 0b101, 0b101, 0b101, 0b010]),
+        ('W', &[0b101, 0b101, 0b111, 0b111, 0b101]),
+        ('X', &[0b101, 0b101, 0b010, 0b101, 0b101]),
+        ('Y', &[0b101, 0b101, 0b010, 0b010, 0b010]),
+        ('Z', &[0b111, 0b001, 0b010, 0b100, 0b111]),
+        ('Ä', &[0b101, 0b010, 0b101, 0b111, 0b101]),
+        ('Ö', &[0b101, 0b000, 0b111, 0b101, 0b111]),
+        ('Ü', &[0b101, 0b000, 0b101, 0b101, 0b111]),
+        ('ß', &[0b110, 0b101, 0b110, 0b101, 0b110]),
+        (' ', &[0b000, 0b000, 0b000, 0b000, 0b000]),
+        ('!', &[0b010, 0b010, 0b010, 0b000, 0b010]),
+        ('?', &[0b110, 0b001, 0b010, 0b000, 0b010]),
+        ('.', &[0b000, 0b000, 0b000, 0b000, 0b010]),
+        (',', &[0b000, 0b000, 0b000, 0b010, 0b100]),
+        (':', &[0b000, 0b010, 0b000, 0b010, 0b000]),
+        ('-', &[0b000, 0b000, 0b111, 0b000, 0b000]),
+        ('+', &[0b000, 0b010, 0b111, 0b010, 0b000]),
+        ('/', &[0b001, 0b001, 0b010, 0b100, 0b100]),
+        ('(', &[0b010, 0b100, 0b100, 0b100, 0b010]),
+        (')', &[0b010, 0b001, 0b001, 0b001, 0b010]),
+        ('=', &[0b000, 0b111, 0b000, 0b111, 0b000]),
+        ('#', &[0b101, 0b111, 0b101, 0b111, 0b101]),
+    ],
+};
+
+/// Large font, best used with the text scroller
+pub static FONT_5X7: Font = Font {
+    width: 5,
+    height: 7,
+    glyphs: &[
+        (
+            '0',
+            &[
+                0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110,
+            ],
+        ),
+        (
+            '1',
+            &[
+                0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110,
+            ],
+        ),
+        (
+            '2',
+            &[
+                0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111,
+            ],
+        ),
+        (
+            '3',
+            &[
+                0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110,
+            ],
+        ),
+        (
+            '4',
+            &[
+                0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010,
+            ],
+        ),
+        (
+            '5',
+            &[
+                0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110,
+            ],
+        ),
+        (
+            '6',
+            &[
+                0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110,
+            ],
+        ),
+        (
+            '7',
+            &[
+                0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000,
+            ],
+        ),
+        (
+            '8',
+            &[
+                0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110,
+            ],
+        ),
+        (
+            '9',
+            &[
+                0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100,
+            ],
+        ),
+        (
+            'A',
+            &[
+                0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001,
+            ],
+        ),
+        (
+            'B',
+            &[
+                0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110,
+            ],
+        ),
+        (
+            'C',
+            &[
+                0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110,
+            ],
+        ),
+        (
+            'D',
+            &[
+                0b11100, 0b10010, 0b10001, 0b10001, 0b10001, 0b10010, 0b11100,
+            ],
+        ),
+        (
+            'E',
+            &[
+                0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111,
+            ],
+        ),
+        (
+            'F',
+            &[
+                0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000,
+            ],
+        ),
+        (
+            'G',
+            &[
+                0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01111,
+            ],
+        ),
+        (
+            'H',
+            &[
+                0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001,
+            ],
+        ),
+        (
+            'I',
+            &[
+                0b01110, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110,
+            ],
+        ),
+        (
+            'J',
+            &[
+                0b00111, 0b00010, 0b00010, 0b00010, 0b00010, 0b10010, 0b01100,
+            ],
+        ),
+        (
+            'K',
+            &[
+                0b10001, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010, 0b10001,
+            ],
+        ),
+        (
+            'L',
+            &[
+                0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111,
+            ],
+        ),
+        (
+            'M',
+            &[
+                0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001,
+            ],
+        ),
+        (
+            'N',
+            &[
+                0b10001, 0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001,
+            ],
+        ),
+        (
+            'O',
+            &[
+                0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110,
+            ],
+        ),
+        (
+            'P',
+            &[
+                0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000,
+            ],
+        ),
+        (
+            'Q',
+            &[
+                0b01110, 0b10001, 0b10001, 0b10001, 0b10101, 0b10010, 0b01101,
+            ],
+        ),
+        (
+            'R',
+            &[
+                0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001,
+            ],
+        ),
+        (
+            'S',
+            &[
+                0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110,
+            ],
+        ),
+        (
+            'T',
+            &[
+                0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100,
+            ],
+        ),
+        (
+            'U',
+            &[
+                0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110,
+            ],
+        ),
+        (
+            'V',
+            &[
+                0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100,
+            ],
+        ),
+        (
+            'W',
+            &[
+                0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b10101, 0b01010,
+            ],
+        ),
+        (
+            'X',
+            &[
+                0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001,
+            ],
+        ),
+        (
+            'Y',
+            &[
+                0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100, 0b00100,
+            ],
+        ),
+        (
+            'Z',
+            &[
+                0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111,
+            ],
+        ),
+        (
+            'Ä',
+            &[
+                0b10001, 0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001,
+            ],
+        ),
+        (
+            'Ö',
+            &[
+                0b10001, 0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110,
+            ],
+        ),
+        (
+            'Ü',
+            &[
+                0b10001, 0b00000, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110,
+            ],
+        ),
+        (
+            'ß',
+            &[
+                0b01100, 0b10010, 0b10010, 0b10100, 0b10010, 0b10010, 0b10100,
+            ],
+        ),
+        (
+            ' ',
+            &[
+                0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000,
+            ],
+        ),
+        (
+            '!',
+            &[
+                0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00000, 0b00100,
+            ],
+        ),
+        (
+            '?',
+            &[
+                0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b00000, 0b00100,
+            ],
+        ),
+        (
+            '.',
+            &[
+                0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b01100,
+            ],
+        ),
+        (
+            ',',
+            &[
+                0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b00100, 0b01000,
+            ],
+        ),
+        (
+            ':',
+            &[
+                0b00000, 0b01100, 0b01100, 0b00000, 0b01100, 0b01100, 0b00000,
+            ],
+        ),
+        (
+            '-',
+            &[
+                0b00000, 0b00000, 0b00000, 0b11111, 0b00000, 0b00000, 0b00000,
+            ],
+        ),
+        (
+            '+',
+            &[
+                0b00000, 0b00100, 0b00100, 0b11111, 0b00100, 0b00100, 0b00000,
+            ],
+        ),
+        (
+            '/',
+            &[
+                0b00000, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b00000,
+            ],
+        ),
+        (
+            '(',
+            &[
+                0b00010, 0b00100, 0b01000, 0b01000, 0b01000, 0b00100, 0b00010,
+            ],
+        ),
+        (
+            ')',
+            &[
+                0b01000, 0b00100, 0b00010, 0b00010, 0b00010, 0b00100, 0b01000,
+            ],
+        ),
+        (
+            '=',
+            &[
+                0b00000, 0b00000, 0b11111, 0b00000, 0b11111, 0b00000, 0b00000,
+            ],
+        ),
+        (
+            '#',
+            &[
+                0b01010, 0b01010, 0b11111, 0b01010, 0b11111, 0b01010, 0b01010,
+            ],
+        ),
+    ],
+};
+
+#[cfg(test)]
+mod test_text {
+    use super::{FONT_3X5, FONT_5X7};
+    use crate::rendering::xy;
+    use rgb::RGB8;
+
+    #[test]
+    fn test_glyph_lookup() {
+        assert_eq!(FONT_5X7.glyph('a'), FONT_5X7.glyph('A'));
+        assert_eq!(FONT_5X7.glyph('ü'), FONT_5X7.glyph('Ü'));
+        assert_eq!(FONT_3X5.glyph('€'), FONT_3X5.glyph('?'));
+        for (_, rows) in FONT_3X5.glyphs {
+            assert_eq!(rows.len(), FONT_3X5.height);
+        }
+        for (_, rows) in FONT_5X7.glyphs {
+            assert_eq!(rows.len(), FONT_5X7.height);
+        }
+    }
+
+    #[test]
+    fn test_text_width() {
+        assert_eq!(FONT_3X5.text_width(""), 0);
+        assert_eq!(FONT_3X5.text_width("1"), 3);
+        assert_eq!(FONT_5X7.text_width("P1 WINS"), 7 * 6 - 1);
+        assert_eq!(FONT_5X7.text_width("ÄÖÜ"), 3 * 6 - 1);
+    }
+
+    #[test]
+    fn test_draw_clipped() {
+        let on = RGB8::new(1, 1, 1);
+        let mut frame = [RGB8::default(); 256];
+        // '1' in the 3x5 font starts with the row .#.
+        FONT_3X5.draw(&mut frame, "1", -1, 0, on);
+        assert!(*xy(&mut frame, 0, 0) == on);
+        assert!(*xy(&mut frame, 1, 0) == RGB8::default());
+
+        // completely outside
+        let mut frame = [RGB8::default(); 256];
+        FONT_5X7.draw(&mut frame, "88", 16, 0, on);
+        FONT_5X7.draw(&mut frame, "88", -11, 0, on);
+        FONT_5X7.draw(&mut frame, "88", 0, 16, on);
+        assert!(frame.iter().all(|p| *p == RGB8::default()));
+    }
+}
@@ -0,0 +1,126 @@
+use rgb::RGB8;
+
+use crate::game::Player;
+
+/// Colors the players can choose from at game start, in row-major order (numpad 7 is the first one).
+/// The LEDs are very bright, so all colors are kept well below full brightness.
+pub const PLAYER_COLOR_CHOICES: [RGB8; 9] = [
+    RGB8::new(0, 80, 0),   // green
+    RGB8::new(0, 0, 80),   // blue
+    RGB8::new(80, 0, 0),   // red
+    RGB8::new(80, 40, 0),  // orange (Okabe-Ito)
+    RGB8::new(20, 50, 80), // sky blue (Okabe-Ito)
+    RGB8::new(60, 60, 0),  // yellow
+    RGB8::new(60, 0, 60),  // magenta
+    RGB8::new(0, 60, 60),  // teal
+    RGB8::new(50, 50, 50), // white
+];
+
+pub struct Theme {
+    pub name: &'static str,
+    /// index into PLAYER_COLOR_CHOICES for player one and two
+    pub player_colors: [u8; 2],
+    /// glow of an illegal move
+    pub error_glow: RGB8,
+    /// glow of the empty cells the current player can choose from
+    pub current_grid_glow: RGB8,
+    /// fill color of a mini-grid which ended in a draw
+    pub draw_color: RGB8,
+}
+
+pub const THEMES: [Theme; 3] = [
+    Theme {
+        name: "Classic",
+        // green vs blue
+        player_colors: [0, 1],
+        error_glow: RGB8::new(10, 0, 0),
+        current_grid_glow: RGB8::new(5, 0, 5),
+        draw_color: RGB8::new(255, 255, 255),
+    },
+    Theme {
+        // orange vs sky blue, safe for red-green colorblindness (protanopia, deuteranopia)
+        name: "Okabe-Ito",
+        player_colors: [3, 4],
+        error_glow: RGB8::new(10, 0, 8),
+        current_grid_glow: RGB8::new(4, 4, 4),
+        draw_color: RGB8::new(255, 255, 255),
+    },
+    Theme {
+        // red vs teal, safe for blue-yellow colorblindness (tritanopia)
+        name: "Tritan",
+        player_colors: [2, 7],
+        error_glow: RGB8::new(10, 0, 10),
+        current_grid_glow: RGB8::new(4, 4, 4),
+        draw_color: RGB8::new(255, 255, 255),
+    },
+];
+
+/// Additional coding to tell the players apart without relying on hue
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PlayerCoding {
+    /// color only
+    Off = 0,
+    /// the cells of player two slowly pulse
+    Blink = 1,
+    /// won mini-grids show a large X (player one) or O (player two)
+    Shape = 2,
+}
+
+impl PlayerCoding {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(PlayerCoding::Off),
+            1 => Some(PlayerCoding::Blink),
+            2 => Some(PlayerCoding::Shape),
+            _ => None,
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            PlayerCoding::Off => PlayerCoding::Blink,
+            PlayerCoding::Blink => PlayerCoding::Shape,
+            PlayerCoding::Shape => PlayerCoding::Off,
+        }
+    }
+}
+
+/// The color related settings, chosen at game start and persisted in flash
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ColorSettings {
+    /// index into THEMES
+    pub theme: u8,
+    /// index into PLAYER_COLOR_CHOICES for player one and two
+    pub player_colors: [u8; 2],
+    pub coding: PlayerCoding,
+    /// how many of the last moves are marked on the board (0..=2)
+    pub marked_moves: u8,
+}
+
+impl Default for ColorSettings {
+    fn default() -> Self {
+        Self::with_theme(0)
+    }
+}
+
+impl ColorSettings {
+    /// the given theme, with its default player colors
+    pub fn with_theme(theme: u8) -> Self {
+        let theme = theme % THEMES.len() as u8;
+        ColorSettings {
+            theme,
+            player_colors: THEMES[theme as usize].player_colors,
+            coding: PlayerCoding::Off,
+            marked_moves: 1,
+        }
+    }
+
+    pub fn theme(&self) -> &'static Theme {
+        &THEMES[self.theme as usize % THEMES.len()]
+    }
+
+    pub fn player_color(&self, player: Player) -> RGB8 {
+        let index = self.player_colors[player as usize - 1] as usize;
+        PLAYER_COLOR_CHOICES[index % PLAYER_COLOR_CHOICES.len()]
+    }
+}
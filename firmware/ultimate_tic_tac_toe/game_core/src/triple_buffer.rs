@@ -0,0 +1,154 @@
+//! Lock-free triple buffer to hand frames from the render task to the LED task.
+//!
+//! The writer always has a slot to render into and the reader always has a slot to send out,
+//! the third slot is shared between them and swapped atomically. Neither side ever waits for
+//! the other, and no memory is allocated after construction.
+//! If the writer publishes twice before the reader takes a frame, the older one is dropped.
+
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// set in `shared` if the shared slot holds a frame the reader hasn't taken yet
+const NEW_FLAG: usize = 0b100;
+const INDEX_MASK: usize = 0b011;
+
+pub struct TripleBuffer<T> {
+    slots: [UnsafeCell<T>; 3],
+    /// index of the shared slot, plus NEW_FLAG
+    shared: AtomicUsize,
+}
+
+// Each slot is only accessed by whoever owns its index, ownership only changes through `shared`
+unsafe impl<T: Send> Sync for TripleBuffer<T> {}
+
+impl<T: Copy> TripleBuffer<T> {
+    pub const fn new(initial: T) -> Self {
+        TripleBuffer {
+            slots: [
+                UnsafeCell::new(initial),
+                UnsafeCell::new(initial),
+                UnsafeCell::new(initial),
+            ],
+            shared: AtomicUsize::new(1),
+        }
+    }
+}
+
+impl<T> TripleBuffer<T> {
+    /// Split into the two halves. Slot 0 starts with the writer, 1 is shared, 2 is the reader's.
+    pub fn split(&mut self) -> (Writer<'_, T>, Reader<'_, T>) {
+        (
+            Writer {
+                buffer: self,
+                index: 0,
+            },
+            Reader {
+                buffer: self,
+                index: 2,
+            },
+        )
+    }
+}
+
+pub struct Writer<'a, T> {
+    buffer: &'a TripleBuffer<T>,
+    index: usize,
+}
+
+impl<T> Writer<'_, T> {
+    /// The slot to write the next frame into. It still contains whatever was written
+    /// into it two or three frames ago.
+    pub fn back(&mut self) -> &mut T {
+        unsafe { &mut *self.buffer.slots[self.index].get() }
+    }
+
+    /// Hand the back slot to the reader.
+    /// Returns false if the previous frame was never taken by the reader and is now dropped.
+    pub fn publish(&mut self) -> bool {
+        let previous = self
+            .buffer
+            .shared
+            .swap(self.index | NEW_FLAG, Ordering::AcqRel);
+        self.index = previous & INDEX_MASK;
+        previous & NEW_FLAG == 0
+    }
+}
+
+pub struct Reader<'a, T> {
+    buffer: &'a TripleBuffer<T>,
+    index: usize,
+}
+
+impl<T> Reader<'_, T> {
+    /// Take the most recently published frame, None if there was no new one since the last call
+    pub fn take(&mut self) -> Option<&T> {
+        if self.buffer.shared.load(Ordering::Acquire) & NEW_FLAG == 0 {
+            return None;
+        }
+        // only the writer can change `shared` in between, and it always sets NEW_FLAG
+        let previous = self.buffer.shared.swap(self.index, Ordering::AcqRel);
+        self.index = previous & INDEX_MASK;
+        Some(unsafe { &*self.buffer.slots[self.index].get() })
+    }
+}
+
+#[cfg(test)]
+mod test_triple_buffer {
+    use super::TripleBuffer;
+
+    #[test]
+    fn test_publish_and_take() {
+        let mut buffer = TripleBuffer::new(0u32);
+        let (mut writer, mut reader) = buffer.split();
+
+        assert_eq!(reader.take(), None);
+
+        *writer.back() = 1;
+        assert!(writer.publish());
+        assert_eq!(reader.take(), Some(&1));
+        assert_eq!(reader.take(), None);
+
+        // the reader only sees the latest frame, the one before is dropped
+        *writer.back() = 2;
+        assert!(writer.publish());
+        *writer.back() = 3;
+        assert!(!writer.publish());
+        assert_eq!(reader.take(), Some(&3));
+
+        // all three slots are in use and still distinct
+        for i in 4..20 {
+            *writer.back() = i;
+            assert!(writer.publish());
+            assert_eq!(reader.take(), Some(&i));
+        }
+    }
+
+    #[test]
+    fn test_across_threads() {
+        extern crate std;
+
+        let mut buffer = TripleBuffer::new([0u32; 64]);
+        let (mut writer, mut reader) = buffer.split();
+
+        std::thread::scope(|s| {
+            s.spawn(move || {
+                for i in 1..=10_000 {
+                    writer.back().fill(i);
+                    writer.publish();
+                }
+            });
+
+            let mut last = 0;
+            while last != 10_000 {
+                if let Some(frame) = reader.take() {
+                    // never a torn frame, and never older than the one before
+                    assert!(frame.iter().all(|v| *v == frame[0]));
+                    assert!(frame[0] > last);
+                    last = frame[0];
+                }
+            }
+        });
+    }
+}
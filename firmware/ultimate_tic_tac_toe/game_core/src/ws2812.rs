@@ -0,0 +1,74 @@
+//! The WS2812 protocol as an SPI bitstream, for the SPI driver of the matrix.
+//!
+//! The SPI runs at 4.5 MHz (see main.rs of the firmware for the SPI config):
+//! every data bit is sent as 4 SPI bits, 0 as 1000 and 1 as 1110.
+//! That's ~0.22 us high for a 0 and ~0.67 us high for a 1, at ~0.89 us per bit.
+
+use rgb::RGB8;
+
+use crate::MATRIX_LENGTH;
+
+const SPI_BITS_PER_BIT: usize = 4;
+const BYTES_PER_LED: usize = 3 * 8 * SPI_BITS_PER_BIT / 8;
+/// The chain latches the data after the line was low for more than 50 us (280 us for newer
+/// WS2812B). 140 bytes of zeros are ~250 us.
+const RESET_BYTES: usize = 140;
+
+/// Size of the DMA buffer needed for the matrix
+pub const ENCODED_FRAME_LEN: usize = MATRIX_LENGTH * BYTES_PER_LED + RESET_BYTES;
+
+/// SPI bits for two data bits, indexed by the two bits
+const PATTERNS: [u8; 4] = [0b1000_1000, 0b1000_1110, 0b1110_1000, 0b1110_1110];
+
+/// Encode the LEDs into the SPI bitstream, including the trailing reset.
+/// Returns the number of bytes written.
+pub fn encode(leds: &[RGB8], out: &mut [u8]) -> usize {
+    let mut i = 0;
+    for led in leds {
+        // the LEDs expect green, red, blue, most significant bit first
+        for byte in [led.g, led.r, led.b] {
+            for shift in [6, 4, 2, 0] {
+                out[i] = PATTERNS[((byte >> shift) & 0b11) as usize];
+                i += 1;
+            }
+        }
+    }
+    out[i..i + RESET_BYTES].fill(0);
+    i + RESET_BYTES
+}
+
+#[cfg(test)]
+mod test_ws2812 {
+    use rgb::RGB8;
+
+    use super::{ENCODED_FRAME_LEN, RESET_BYTES, encode};
+    use crate::MATRIX_LENGTH;
+
+    #[test]
+    fn test_encode() {
+        let mut frame = [RGB8::default(); MATRIX_LENGTH];
+        frame[0] = RGB8::new(0xFF, 0x00, 0b1001_0110);
+
+        let mut out = [0xAAu8; ENCODED_FRAME_LEN];
+        assert_eq!(encode(&frame, &mut out), ENCODED_FRAME_LEN);
+
+        // green first
+        assert_eq!(out[0..4], [0b1000_1000; 4]);
+        assert_eq!(out[4..8], [0b1110_1110; 4]);
+        assert_eq!(
+            out[8..12],
+            [0b1110_1000, 0b1000_1110, 0b1000_1110, 0b1110_1000]
+        );
+        // all other LEDs are off, followed by the reset
+        assert!(
+            out[12..ENCODED_FRAME_LEN - RESET_BYTES]
+                .iter()
+                .all(|b| *b == 0b1000_1000)
+        );
+        assert!(
+            out[ENCODED_FRAME_LEN - RESET_BYTES..]
+                .iter()
+                .all(|b| *b == 0)
+        );
+    }
+}
@@ -0,0 +1,328 @@
+//! Zoomed view of the mini-grid in which the current player has to pick a cell.
+//!
+//! The selected mini-grid fills the matrix with 4x4 pixel cells (3x3 lit, with a one pixel gap).
+//! The other mini-grids are shown as 2x2 markers around the edges, each at the same place as on
+//! the board, so the slot of the zoomed mini-grid stays empty. The center mini-grid has no place
+//! on the edge, it is marked in the gaps around the center cell instead.
+
+use rgb::RGB8;
+
+use crate::{
+    Frame, MATRIX_LENGTH,
+    game::{BoardState, GameStage, NextUserSelection, Player, PlayerOrDraw},
+    rendering::{
+        FOCUS_BRIGHTNESS, TARGET_FRAME_BRIGHTNESS, add_scaled, glow_pulse,
+        occupied_cell_brightness, render_frame, xy,
+    },
+    theme::ColorSettings,
+};
+
+/// distance between two cells of the zoomed mini-grid, in pixels
+const CELL_PITCH: usize = 4;
+/// size of the lit part of a cell
+const CELL_SIZE: usize = 3;
+/// position of the top-left cell
+const GRID_OFFSET: usize = 2;
+
+/// top-left corners of the 2x2 markers of the mini-grids on the edge, in board order
+const MARKER_POSITIONS: [Option<(usize, usize)>; 9] = [
+    Some((0, 0)),
+    Some((7, 0)),
+    Some((14, 0)),
+    Some((0, 7)),
+    None,
+    Some((14, 7)),
+    Some((0, 14)),
+    Some((7, 14)),
+    Some((14, 14)),
+];
+/// the pixels which mark the center mini-grid, where the gaps around the center cell cross
+const CENTER_MARKER: [(usize, usize); 4] = [(5, 5), (9, 5), (5, 9), (9, 9)];
+
+/// The pixels of the marker of the mini-grid `i_board` (0 based)
+fn marker_pixels(i_board: usize) -> [(usize, usize); 4] {
+    match MARKER_POSITIONS[i_board] {
+        Some((left, top)) => [
+            (left, top),
+            (left + 1, top),
+            (left, top + 1),
+            (left + 1, top + 1),
+        ],
+        None => CENTER_MARKER,
+    }
+}
+
+/// brightness of the marker of a mini-grid which is still open
+const OPEN_MARKER: RGB8 = RGB8::new(6, 6, 6);
+
+/// duration of the zoom in or out, in seconds
+const ZOOM_TIME: f32 = 0.4;
+/// after a move, the full board is shown at least this long, in seconds
+const MOVE_OVERVIEW_TIME: f32 = 1.2;
+
+/// The mini-grid (0 based) which the zoomed view would show for `game_stage`
+fn zoomed_grid(game_stage: &GameStage) -> Option<(BoardState, usize)> {
+    match *game_stage {
+        GameStage::InProgress(state, NextUserSelection::SelectCell(grid, _))
+        | GameStage::IllegalMove(state, NextUserSelection::SelectCell(grid, _), _) => {
+            Some((state, grid as usize - 1))
+        }
+        _ => None,
+    }
+}
+
+/// The focused cell (0 based) if it is empty, and which mini-grids a move there would send
+/// the opponent to
+fn focus_preview(game_stage: &GameStage) -> Option<(usize, [bool; 9])> {
+    let GameStage::InProgress(state, NextUserSelection::SelectCell(grid, Some(cell))) = *game_stage
+    else {
+        return None;
+    };
+    if state.board[grid as usize - 1][cell as usize - 1].is_some() {
+        return None;
+    }
+    let targets = match state.move_target(grid, cell) {
+        Some(target) => core::array::from_fn(|i| i == target as usize - 1),
+        None => state.open_grids_after_move(grid, cell),
+    };
+    Some((cell as usize - 1, targets))
+}
+
+/// Render the zoomed view, None if the current player doesn't select a cell
+pub fn render_zoomed(
+    game_stage: &GameStage,
+    color_settings: &ColorSettings,
+    stage_elapsed: f32,
+) -> Option<Frame> {
+    let (board_state, i_grid) = zoomed_grid(game_stage)?;
+    let mut colors = [RGB8::default(); MATRIX_LENGTH];
+
+    let illegal_cell = match *game_stage {
+        GameStage::IllegalMove(_, _, played_move) if played_move.grid as usize - 1 == i_grid => {
+            Some(played_move.cell as usize - 1)
+        }
+        _ => None,
+    };
+
+    let player_color = color_settings.player_color(board_state.current_player);
+    let focus = focus_preview(game_stage);
+    let glow = glow_pulse(color_settings, stage_elapsed);
+    for i_cell in 0..9 {
+        let color = match board_state.board[i_grid][i_cell] {
+            Some(player) => {
+                let mut color = RGB8::default();
+                add_scaled(
+                    &mut color,
+                    color_settings.player_color(player),
+                    occupied_cell_brightness(
+                        color_settings,
+                        &board_state,
+                        i_grid,
+                        i_cell,
+                        stage_elapsed,
+                    ),
+                );
+                color
+            }
+            None if focus.is_some_and(|(focused, _)| focused == i_cell) => {
+                let mut color = RGB8::default();
+                add_scaled(&mut color, player_color, FOCUS_BRIGHTNESS);
+                color
+            }
+            None => glow,
+        };
+        let color = if Some(i_cell) == illegal_cell {
+            // same 2 Hz pulse as in the full view
+            let env = (1.0 + libm::cosf(2.0 * core::f32::consts::PI * 2.0 * stage_elapsed)) * 0.5;
+            let mut error = RGB8::default();
+            add_scaled(&mut error, color_settings.theme().error_glow, env);
+            add_scaled(&mut error, color, 1.0 - env);
+            error
+        } else {
+            color
+        };
+
+        let left = GRID_OFFSET + (i_cell % 3) * CELL_PITCH;
+        let top = GRID_OFFSET + (i_cell / 3) * CELL_PITCH;
+        for y in top..top + CELL_SIZE {
+            for x in left..left + CELL_SIZE {
+                *xy(&mut colors, x, y) = color;
+            }
+        }
+    }
+
+    for i_board in (0..9).filter(|&i| i != i_grid) {
+        let color = match board_state.finished_grids[i_board] {
+            Some(PlayerOrDraw::Player(p)) => color_settings.player_color(p),
+            Some(PlayerOrDraw::Draw) => {
+                // the draw color is meant for a dim glow, at full brightness it would dazzle
+                let mut color = RGB8::default();
+                add_scaled(&mut color, color_settings.theme().draw_color, 0.2);
+                color
+            }
+            // a mini-grid the focused cell would send the opponent to
+            None if focus.is_some_and(|(_, targets)| targets[i_board]) => {
+                let mut color = RGB8::default();
+                add_scaled(
+                    &mut color,
+                    color_settings.player_color(board_state.current_player.other()),
+                    TARGET_FRAME_BRIGHTNESS,
+                );
+                color
+            }
+            None => OPEN_MARKER,
+        };
+        for (x, y) in marker_pixels(i_board) {
+            *xy(&mut colors, x, y) = color;
+        }
+    }
+
+    // current player, in the gaps between the markers of the top or bottom row
+    let y = match board_state.current_player {
+        Player::PlayerOne => 0,
+        Player::PlayerTwo => 15,
+    };
+    for x in (3..6).chain(10..13) {
+        *xy(&mut colors, x, y) = player_color;
+    }
+
+    Some(colors)
+}
+
+/// Blend from `from` to `to`, `amount` is 0..1
+fn crossfade(from: &Frame, to: &Frame, amount: f32) -> Frame {
+    let amount = amount.clamp(0.0, 1.0);
+    let mut colors = [RGB8::default(); MATRIX_LENGTH];
+    for (i, pixel) in colors.iter_mut().enumerate() {
+        add_scaled(pixel, from[i], 1.0 - amount);
+        add_scaled(pixel, to[i], amount);
+    }
+    colors
+}
+
+/// Render a frame with the zoomed view enabled, including the transitions from `previous`.
+///
+/// When a player selects a mini-grid, the view zooms in. After a move, it zooms out to the full
+/// board, shows it for a moment, and then zooms into the next mini-grid.
+/// An illegal move stays in the zoomed view.
+pub fn render_with_zoom(
+    previous: &GameStage,
+    game_stage: &GameStage,
+    color_settings: &ColorSettings,
+    stage_elapsed: f32,
+) -> Frame {
+    let full = render_frame(game_stage, color_settings, stage_elapsed);
+    let zoomed = render_zoomed(game_stage, color_settings, stage_elapsed);
+
+    let from = zoomed_grid(previous);
+    let to = zoomed_grid(game_stage);
+    let same_grid = matches!(
+        (from, to),
+        (Some((before, grid_before)), Some((after, grid)))
+            if grid_before == grid && before.board == after.board
+    );
+    if same_grid {
+        // still in the same mini-grid, e.g. after an illegal move
+        return zoomed.unwrap_or(full);
+    }
+
+    // zoom out of the previous mini-grid
+    let zooming_out = render_zoomed(previous, color_settings, stage_elapsed)
+        .filter(|_| stage_elapsed < ZOOM_TIME);
+    if let Some(previous_zoomed) = zooming_out {
+        return crossfade(&previous_zoomed, &full, stage_elapsed / ZOOM_TIME);
+    }
+
+    // zoom into the mini-grid of this stage
+    let (Some(zoomed), Some((after, _))) = (zoomed, to) else {
+        return full;
+    };
+    let zoom_in_start = match from {
+        Some((before, _)) if before.board != after.board => MOVE_OVERVIEW_TIME,
+        Some(_) => ZOOM_TIME,
+        None => 0.0,
+    };
+    if stage_elapsed < zoom_in_start {
+        full
+    } else {
+        crossfade(&full, &zoomed, (stage_elapsed - zoom_in_start) / ZOOM_TIME)
+    }
+}
+
+#[cfg(test)]
+mod test_zoom {
+    use rgb::RGB8;
+
+    use super::{OPEN_MARKER, render_with_zoom, render_zoomed};
+    use crate::{
+        game::{BoardState, GameStage, NextUserSelection, Player},
+        rendering::{render_frame, xy},
+        samples,
+        theme::ColorSettings,
+    };
+
+    #[test]
+    fn test_only_when_selecting_a_cell() {
+        let settings = ColorSettings::default();
+        let select_grid = GameStage::InProgress(BoardState::new(), NextUserSelection::SelectGrid);
+        assert_eq!(render_zoomed(&select_grid, &settings, 0.0), None);
+        assert!(render_zoomed(&samples::by_name("select_cell").unwrap(), &settings, 0.0).is_some());
+    }
+
+    #[test]
+    fn test_transitions() {
+        let settings = ColorSettings::default();
+        let select_cell = samples::by_name("select_cell").unwrap();
+        let illegal_move = samples::by_name("illegal_move").unwrap();
+        // player one takes the bottom-left cell of the top-center mini-grid
+        let GameStage::InProgress(mut board, _) = select_cell else {
+            unreachable!()
+        };
+        board.board[1][6] = Some(Player::PlayerOne);
+        board.current_player = Player::PlayerTwo;
+        let player_two = GameStage::InProgress(board, NextUserSelection::SelectCell(7, None));
+        let full = |stage: &GameStage, t: f32| render_frame(stage, &settings, t);
+        let zoomed = |stage: &GameStage, t: f32| render_zoomed(stage, &settings, t).unwrap();
+
+        // an illegal move stays zoomed in
+        assert_eq!(
+            render_with_zoom(&select_cell, &illegal_move, &settings, 0.0),
+            zoomed(&illegal_move, 0.0)
+        );
+
+        // after a move: the full board, then zoomed into the next mini-grid
+        assert_eq!(
+            render_with_zoom(&select_cell, &player_two, &settings, 0.5),
+            full(&player_two, 0.5)
+        );
+        assert_eq!(
+            render_with_zoom(&select_cell, &player_two, &settings, 2.0),
+            zoomed(&player_two, 2.0)
+        );
+
+        // the first frame after the move still shows the previous mini-grid
+        assert_eq!(
+            render_with_zoom(&select_cell, &player_two, &settings, 0.0),
+            zoomed(&select_cell, 0.0)
+        );
+    }
+
+    #[test]
+    fn test_marker_places() {
+        let settings = ColorSettings::default();
+        // zoomed into the top-right mini-grid, the center cell sends the opponent to the center
+        let mut frame =
+            render_zoomed(&samples::by_name("focus_corner").unwrap(), &settings, 0.25).unwrap();
+        let mut pixel = |x, y| *xy(&mut frame, x, y);
+        // the slot of the zoomed mini-grid is empty
+        assert_eq!(pixel(14, 0), RGB8::default());
+        // the top-center mini-grid keeps its place, the top-left one is won by player one
+        assert_eq!(pixel(7, 0), OPEN_MARKER);
+        assert_eq!(pixel(0, 0), settings.player_color(Player::PlayerOne));
+        // the center mini-grid is the target of the focused cell
+        let target = pixel(5, 5);
+        assert_ne!(target, OPEN_MARKER);
+        assert_ne!(target, RGB8::default());
+        assert_eq!(pixel(9, 9), target);
+    }
+}
@@ -0,0 +1,241 @@
+//! Golden-image tests for the renderer.
+//!
+//! Every case renders one of the sample stages at a fixed time and compares it against
+//! `tests/golden/<case>.png`, one pixel per LED with the raw LED values.
+//!
+//! On a mismatch the actual frame is written next to the golden image as `<case>.new.png`,
+//! so both can be compared in an image viewer (or with `cargo run` in `../preview`).
+//! After an intended change, update all golden images with
+//!
+//! ```sh
+//! UPDATE_GOLDEN=1 cargo test --test golden_images
+//! ```
+//!
+//! and review the changed PNGs in the diff before committing them.
+
+use std::{fs::File, io::BufWriter, path::PathBuf};
+
+use game_core::{
+    Frame, MATRIX_HEIGHT, MATRIX_WIDTH,
+    idle::{IdlePhase, render_idle},
+    rendering::{render_frame, render_keyboard_icon, xy},
+    samples,
+    theme::{ColorSettings, PlayerCoding},
+    zoom::render_with_zoom,
+};
+use rgb::RGB8;
+
+struct Case {
+    name: &'static str,
+    sample: &'static str,
+    seconds: f32,
+    coding: PlayerCoding,
+    zoom: bool,
+    marked_moves: u8,
+    idle: IdlePhase,
+    /// no keyboard connected, which hides the game
+    no_keyboard: bool,
+}
+
+const fn case(name: &'static str, sample: &'static str, seconds: f32) -> Case {
+    Case {
+        name,
+        sample,
+        seconds,
+        coding: PlayerCoding::Off,
+        zoom: false,
+        marked_moves: 1,
+        idle: IdlePhase::Active,
+        no_keyboard: false,
+    }
+}
+
+const CASES: &[Case] = &[
+    case("choose_color", "choose_color", 0.25),
+    // selection glow, at its peak and in between
+    case("start", "start", 0.0),
+    case("select_grid", "select_grid", 0.25),
+    case("select_cell", "select_cell", 0.25),
+    case("select_cell_dim", "select_cell", 0.5),
+    case("player_two", "player_two", 0.25),
+    // the blink of the last move, at its darkest, and with the last two moves marked
+    case("last_move", "select_grid", 0.5),
+    Case {
+        marked_moves: 2,
+        ..case("last_two_moves", "select_grid", 0.5)
+    },
+    // preview of the mini-grid the focused cell sends the opponent to
+    case("focus", "focus", 0.25),
+    case("focus_free_choice", "focus_free_choice", 0.25),
+    // the error glow fades out
+    case("illegal_move", "illegal_move", 0.0),
+    case("illegal_move_fading", "illegal_move", 0.6),
+    // board with the sweep over the winning line, then the result text
+    case("won", "won", 0.5),
+    case("won_text", "won", 5.0),
+    case("draw", "draw", 0.0),
+    case("draw_text", "draw", 5.0),
+    // additional player codings
+    Case {
+        coding: PlayerCoding::Shape,
+        ..case("select_grid_shape", "select_grid", 0.25)
+    },
+    Case {
+        coding: PlayerCoding::Blink,
+        ..case("player_two_blink", "player_two", 0.5)
+    },
+    // zoomed into the mini-grid of the current player
+    Case {
+        zoom: true,
+        ..case("select_cell_zoom", "select_cell", 0.25)
+    },
+    Case {
+        zoom: true,
+        ..case("illegal_move_zoom", "illegal_move", 0.0)
+    },
+    Case {
+        zoom: true,
+        ..case("focus_free_choice_zoom", "focus_free_choice", 0.25)
+    },
+    // a corner mini-grid, the markers keep their places on the board
+    Case {
+        zoom: true,
+        ..case("focus_corner_zoom", "focus_corner", 0.25)
+    },
+    // idle mode, after the fade into the phase
+    Case {
+        idle: IdlePhase::Dimmed,
+        ..case("select_cell_dimmed", "select_cell", 2.0)
+    },
+    Case {
+        idle: IdlePhase::Attract,
+        ..case("attract", "select_cell", 2.0)
+    },
+    // waiting for a keyboard
+    Case {
+        no_keyboard: true,
+        ..case("keyboard_icon", "select_cell", 0.0)
+    },
+];
+
+fn golden_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+        .join(name)
+}
+
+/// The frame as RGB image, row by row (the frame itself is in strip order)
+fn to_image(frame: &Frame) -> Vec<u8> {
+    let mut frame = *frame;
+    let mut image = Vec::with_capacity(frame.len() * 3);
+    for y in 0..MATRIX_HEIGHT {
+        for x in 0..MATRIX_WIDTH {
+            let c = *xy(&mut frame, x, y);
+            image.extend_from_slice(&[c.r, c.g, c.b]);
+        }
+    }
+    image
+}
+
+fn write_png(path: &PathBuf, image: &[u8]) {
+    let file = File::create(path).unwrap_or_else(|e| panic!("create {}: {e}", path.display()));
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(file),
+        MATRIX_WIDTH as u32,
+        MATRIX_HEIGHT as u32,
+    );
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(image))
+        .unwrap_or_else(|e| panic!("write {}: {e}", path.display()));
+}
+
+fn read_png(path: &PathBuf) -> Option<Vec<u8>> {
+    let decoder = png::Decoder::new(File::open(path).ok()?);
+    let mut reader = decoder.read_info().ok()?;
+    let mut image = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut image).ok()?;
+    if info.width as usize != MATRIX_WIDTH
+        || info.height as usize != MATRIX_HEIGHT
+        || info.color_type != png::ColorType::Rgb
+    {
+        return None;
+    }
+    image.truncate(info.buffer_size());
+    Some(image)
+}
+
+/// Describe the differing LEDs, at most a few of them
+fn describe_diff(expected: &[u8], actual: &[u8]) -> String {
+    let differing: Vec<_> = (0..MATRIX_WIDTH * MATRIX_HEIGHT)
+        .filter(|i| expected[i * 3..i * 3 + 3] != actual[i * 3..i * 3 + 3])
+        .collect();
+    let mut text = format!("{} LEDs differ", differing.len());
+    for i in differing.iter().take(5) {
+        let rgb = |image: &[u8]| RGB8::new(image[i * 3], image[i * 3 + 1], image[i * 3 + 2]);
+        text += &format!(
+            "\n  x={} y={}: expected {}, got {}",
+            i % MATRIX_WIDTH,
+            i / MATRIX_WIDTH,
+            rgb(expected),
+            rgb(actual)
+        );
+    }
+    text
+}
+
+#[test]
+fn test_golden_images() {
+    let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+    let mut failures = Vec::new();
+
+    for case in CASES {
+        let stage = samples::by_name(case.sample)
+            .unwrap_or_else(|| panic!("unknown sample '{}'", case.sample));
+        let color_settings = ColorSettings {
+            coding: case.coding,
+            marked_moves: case.marked_moves,
+            ..ColorSettings::default()
+        };
+        let frame = if case.no_keyboard {
+            render_keyboard_icon(case.seconds)
+        } else if case.zoom {
+            render_with_zoom(&stage, &stage, &color_settings, case.seconds)
+        } else {
+            render_frame(&stage, &color_settings, case.seconds)
+        };
+        let actual = to_image(&render_idle(&frame, case.idle, case.seconds));
+
+        let path = golden_path(&format!("{}.png", case.name));
+        let new_path = golden_path(&format!("{}.new.png", case.name));
+        if update {
+            write_png(&path, &actual);
+            let _ = std::fs::remove_file(&new_path);
+            continue;
+        }
+
+        match read_png(&path) {
+            Some(expected) if expected == actual => {
+                let _ = std::fs::remove_file(&new_path);
+            }
+            expected => {
+                write_png(&new_path, &actual);
+                let reason = match expected {
+                    Some(expected) => describe_diff(&expected, &actual),
+                    None => "golden image missing or unreadable".into(),
+                };
+                failures.push(format!("{}: {reason}", case.name));
+            }
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "golden images differ, see tests/golden/*.new.png \
+         (run with UPDATE_GOLDEN=1 to accept the changes):\n{}",
+        failures.join("\n")
+    );
+}
@@ -0,0 +1,84 @@
+//! Optional SSD1306 OLED on I2C, shows the status of the game as text.
+//!
+//! The display runs in its own task and only uses async I2C transfers, so the render task on
+//! the same core keeps its frame rate while the display is updated.
+
+use embassy_futures::select::{Either, select};
+use embassy_time::{Duration, Instant, Timer};
+use embedded_graphics::{
+    mono_font::{MonoTextStyle, ascii::FONT_6X10},
+    pixelcolor::BinaryColor,
+    prelude::*,
+    text::{Baseline, Text},
+};
+use esp_hal::{Async, i2c::master::I2c};
+use esp_println::println;
+use game_core::{game::GameStage, status_text::write_status};
+use ssd1306::{I2CDisplayInterface, Ssd1306Async, prelude::*};
+
+use crate::game::GameStageReceiver;
+
+/// Measures the duration of the current game, it stops when the game is over
+#[derive(Default)]
+struct GameClock {
+    started: Option<Instant>,
+    ended: Option<Instant>,
+}
+
+impl GameClock {
+    fn update(&mut self, game_stage: &GameStage) {
+        match game_stage {
+            GameStage::ChooseColor(_) => *self = GameClock::default(),
+            GameStage::InProgress(_, _) | GameStage::IllegalMove(_, _, _) => {
+                self.started.get_or_insert_with(Instant::now);
+            }
+            GameStage::Won(_, _) | GameStage::Draw(_) => {
+                self.ended.get_or_insert_with(Instant::now);
+            }
+        }
+    }
+
+    fn seconds(&self) -> u32 {
+        match self.started {
+            Some(started) => (self.ended.unwrap_or_else(Instant::now) - started).as_secs() as u32,
+            None => 0,
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub async fn display_task(i2c: I2c<'static, Async>, mut game_stages: GameStageReceiver) {
+    let interface = I2CDisplayInterface::new(i2c);
+    let mut display = Ssd1306Async::new(interface, DisplaySize128x64, DisplayRotation::Rotate0)
+        .into_buffered_graphics_mode();
+    if let Err(e) = display.init().await {
+        // the display is optional, without it this task just ends
+        println!("No OLED display found: {:?}", e);
+        return;
+    }
+    println!("Display task started");
+
+    let style = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
+    let mut game_stage = game_stages.get().await;
+    let mut clock = GameClock::default();
+
+    loop {
+        clock.update(&game_stage);
+
+        let mut text = heapless::String::<256>::new();
+        // the text is short enough, in the worst case it's cut off
+        let _ = write_status(&mut text, &game_stage, clock.seconds());
+        display.clear_buffer();
+        let _ = Text::with_baseline(&text, Point::zero(), style, Baseline::Top).draw(&mut display);
+        if let Err(e) = display.flush().await {
+            println!("Failed to update the display: {:?}", e);
+        }
+
+        // redraw on every change, and once per second for the clock
+        if let Either::First(new_stage) =
+            select(game_stages.changed(), Timer::after(Duration::from_secs(1))).await
+        {
+            game_stage = new_stage;
+        }
+    }
+}
@@ -1,77 +1,49 @@
-use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_futures::select::{Either, select};
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex,
+    channel::Receiver,
+    signal::Signal,
+    watch::{self, Watch},
+};
+use embassy_time::{Duration, Instant, with_timeout};
+use esp_println::println;
+use game_core::{
+    game::{
+        BoardState, Direction, GameStage, Move, NextUserSelection, Player, PlayerOrDraw, move_focus,
+    },
+    idle::{IdlePhase, IdleTimeouts},
+    pairing::{KeyboardId, Keyboards},
+    settings::Settings,
+    theme::{ColorSettings, THEMES},
+};
+use heapless::Deque;
 use matlab_code::{UltimateInput, UltimateOutput, initialize, run_ultimate};
 
-#[derive(Clone, Copy, PartialEq, Debug)]
-pub enum Player {
-    PlayerOne = 1,
-    PlayerTwo = 2,
-}
-
-#[derive(Clone, Copy, PartialEq, Debug)]
-pub enum PlayerOrDraw {
-    Player(Player),
-    Draw,
-}
-
-impl Player {
-    pub fn from_u8(value: u8) -> Option<Self> {
-        match value {
-            1 => Some(Player::PlayerOne),
-            2 => Some(Player::PlayerTwo),
-            _ => None,
-        }
-    }
-}
-
-#[derive(Copy, Clone, Debug)]
-pub struct BoardState {
-    /// first index is sub-grid, second index is cell within sub-grid.
-    /// Both are in row-major order
-    pub board: [[Option<Player>; 9]; 9],
-    /// Row Major order (top-left, top-center, ...)
-    pub finished_grids: [Option<PlayerOrDraw>; 9],
-    pub current_player: Player,
-}
-
-/// what the user is currently selecting
-#[derive(Copy, Clone, Debug)]
-pub enum NextUserSelection {
-    /// the user must first select a mini-grid (1..9)
-    SelectGrid,
-    /// a mini-grid is selected, user must select a cell (1..9)
-    SelectCell(/*grid*/ u8),
-}
+use crate::{
+    input_queue::{self, InputEvent, InputReceiver},
+    settings::StoredSettings,
+    tinyusb_callbacks::UsbEvent,
+};
 
-#[derive(Copy, Clone, Debug)]
-pub struct Move {
-    pub grid: u8, // 1..9
-    pub cell: u8, // 1..9
-}
+/// Number of tasks following the game stage: the render task, the display task and the
+/// keyboard LED task
+pub const GAME_STAGE_RECEIVERS: usize = 3;
+/// The current game stage, every receiver sees every change
+pub type GameStageWatch = Watch<CriticalSectionRawMutex, GameStage, GAME_STAGE_RECEIVERS>;
+pub type GameStageSender =
+    watch::Sender<'static, CriticalSectionRawMutex, GameStage, GAME_STAGE_RECEIVERS>;
+pub type GameStageReceiver =
+    watch::Receiver<'static, CriticalSectionRawMutex, GameStage, GAME_STAGE_RECEIVERS>;
 
-#[derive(Copy, Clone, Debug)]
-pub enum GameStage {
-    InProgress(BoardState, NextUserSelection),
-    /// same as InProgress, but the last move was illegal
-    IllegalMove(
-        BoardState,
-        NextUserSelection,
-        /*previous_move_attempt*/ Move,
-    ),
-    Won(Player, BoardState),
-    Draw(BoardState),
+/// The game rules are implemented in MATLAB, this connects them to the BoardState
+pub trait MatlabRules {
+    fn board_as_u8_array(&self) -> [u8; 81];
+    fn make_move(self, proposed_grid: u8, proposed_cell: u8) -> GameStage;
 }
 
-impl BoardState {
-    pub fn new() -> Self {
-        BoardState {
-            board: [[None; 9]; 9],
-            current_player: Player::PlayerOne,
-            finished_grids: [None; 9],
-        }
-    }
-
+impl MatlabRules for BoardState {
     // Convert board to u8 array for MATLAB code (flattened 9x9)
-    pub fn board_as_u8_array(&self) -> [u8; 81] {
+    fn board_as_u8_array(&self) -> [u8; 81] {
         // MATLAB uses column-major ordering. The generated C code (from MATLAB)
         // expects the 9x9 array flattened such that element (r,c) maps to
         // index = r + c*9 where r and c are 0-based.
@@ -97,7 +69,7 @@ impl BoardState {
         result
     }
 
-    pub fn make_move(self, proposed_grid: u8, proposed_cell: u8) -> GameStage {
+    fn make_move(self, proposed_grid: u8, proposed_cell: u8) -> GameStage {
         // Prepare input for MATLAB generated function
         // Build current_grid_winners from our finished_grids in column-major order
         let mut cg_winners = [0u8; 9];
@@ -168,13 +140,22 @@ impl BoardState {
         let next_selection = if next_grid == 0 {
             NextUserSelection::SelectGrid
         } else {
-            NextUserSelection::SelectCell(next_grid)
+            NextUserSelection::SelectCell(next_grid, None)
         };
 
+        let played = Move {
+            grid: proposed_grid,
+            cell: proposed_cell,
+        };
         let new_state = BoardState {
             board: new_board,
             current_player: Player::from_u8(next_player_turn).unwrap_or(Player::PlayerOne),
             finished_grids: new_finished,
+            last_moves: if was_legal != 0 {
+                [Some(played), self.last_moves[0]]
+            } else {
+                self.last_moves
+            },
         };
 
         if was_legal != 0 {
@@ -187,111 +168,448 @@ impl BoardState {
                 GameStage::InProgress(new_state, next_selection)
             }
         } else {
-            GameStage::IllegalMove(
-                new_state,
-                next_selection,
-                Move {
-                    grid: proposed_grid,
-                    cell: proposed_cell,
-                },
-            )
+            GameStage::IllegalMove(new_state, next_selection, played)
         }
     }
 }
 
-impl BoardState {
-    pub fn is_draw(&self) -> bool {
-        // it's a draw if all 81 cells are filled, or all sub grids are finished
-        for i_board in 0..9 {
-            for i_cell in 0..9 {
-                if self.board[i_board][i_cell].is_none() {
-                    return false;
-                }
-            }
+/// An input of the game, from a key of a keyboard or a control of a gamepad
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Input {
+    Numpad(u8),
+    Up,
+    Down,
+    Left,
+    Right,
+    /// Enter, or the confirm button of a gamepad
+    Confirm,
+    /// drop the cursor
+    Cancel,
+    /// take back the last move
+    Undo,
+    /// change the settings (the color choice), the game resumes afterwards
+    Menu,
+    /// abort the game, it has to be pressed twice
+    NewGame,
+    /// the other player starts the next game
+    SwapStarter,
+    /// turn the zoomed view on or off, in the settings
+    ToggleZoom,
+    /// how many of the last moves blink during the game (0-2), in the settings
+    MarkedMoves(u8),
+}
+
+impl Input {
+    /// Commands control the game instead of playing it, they work in every stage and from
+    /// every keyboard
+    fn is_command(self) -> bool {
+        matches!(
+            self,
+            Input::Undo | Input::Menu | Input::NewGame | Input::SwapStarter
+        )
+    }
+}
+
+/// Moves which can be taken back
+const UNDO_DEPTH: usize = 16;
+/// The second press of the new game command has to follow the first one within this time
+const NEW_GAME_CONFIRMATION: Duration = Duration::from_secs(3);
+
+/// Remember the stage before a move for undo, unless the move was illegal.
+/// The oldest stage is dropped when the history is full.
+fn remember_move(history: &mut Deque<GameStage, UNDO_DEPTH>, before: GameStage, after: &GameStage) {
+    if matches!(after, GameStage::IllegalMove(_, _, _)) {
+        return;
+    }
+    if history.is_full() {
+        history.pop_front();
+    }
+    let _ = history.push_back(before);
+}
+
+/// Map numpad numbering (1 is bottom-left) to the row-major 1..9 ordering used in MATLAB
+fn numpad_to_row_major(n: u8) -> u8 {
+    match n {
+        1 => 7,
+        2 => 8,
+        3 => 9,
+        4 => 4,
+        5 => 5,
+        6 => 6,
+        7 => 1,
+        8 => 2,
+        9 => 3,
+        _ => unreachable!(),
+    }
+}
+
+impl Input {
+    /// The direction of the arrow keys and the d-pad, which move the cursor
+    fn direction(self) -> Option<Direction> {
+        match self {
+            Input::Up => Some(Direction::Up),
+            Input::Down => Some(Direction::Down),
+            Input::Left => Some(Direction::Left),
+            Input::Right => Some(Direction::Right),
+            _ => None,
         }
-        // Check if all sub-grids are finished
-        for i in 0..9 {
-            if self.finished_grids[i].is_none() {
+    }
+}
+
+/// Handle input while `player` chooses their color.
+/// Returns true if the player is done choosing.
+fn choose_color(settings: &mut ColorSettings, player: Player, input: Input) -> bool {
+    let own = player as usize - 1;
+    let other = 1 - own;
+    match input {
+        Input::Numpad(n) if (1..=9).contains(&n) => {
+            let color = numpad_to_row_major(n) - 1;
+            if color == settings.player_colors[other] {
+                // both players can't have the same color
                 return false;
             }
+            settings.player_colors[own] = color;
+            true
         }
-        true
+        // keep the current color
+        Input::Confirm => true,
+        Input::Left | Input::Right => {
+            let step = if input == Input::Right {
+                1
+            } else {
+                THEMES.len() - 1
+            };
+            let theme = (settings.theme as usize + step) % THEMES.len();
+            *settings = ColorSettings {
+                coding: settings.coding,
+                marked_moves: settings.marked_moves,
+                ..ColorSettings::with_theme(theme as u8)
+            };
+            println!("Theme: {}", settings.theme().name);
+            false
+        }
+        Input::Up | Input::Down => {
+            settings.coding = settings.coding.next();
+            println!("Player coding: {:?}", settings.coding);
+            false
+        }
+        // the stored settings allow at most two
+        Input::MarkedMoves(n) if n <= 2 => {
+            settings.marked_moves = n;
+            println!("Marked moves: {}", n);
+            false
+        }
+        _ => false,
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Debug)]
-pub enum KeyboardInput {
-    Numpad(u8),
-    Number(u8),
-    ArrowUp,
-    ArrowDown,
-    ArrowLeft,
-    ArrowRight,
-    Enter,
+/// Tracks the time since the last key press and tells the render task when to dim the matrix
+/// or start the attract animation
+struct IdleTimer {
+    timeouts: IdleTimeouts,
+    last_input: Instant,
+    phase: IdlePhase,
+    output: &'static Signal<CriticalSectionRawMutex, IdlePhase>,
+}
+
+impl IdleTimer {
+    /// Restart the timer, as if a key was pressed
+    fn reset(&mut self) {
+        self.last_input = Instant::now();
+        self.set_phase(IdlePhase::Active);
+    }
+
+    fn set_phase(&mut self, phase: IdlePhase) {
+        if phase != self.phase {
+            println!("Idle phase: {:?}", phase);
+            self.phase = phase;
+            self.output.signal(phase);
+        }
+    }
+
+    /// Wait for the next key meant for the game.
+    /// A key which ends the attract animation only wakes the board, it isn't passed on,
+    /// as the players couldn't see the board when they pressed it.
+    async fn next_input(&mut self, input: &InputReceiver) -> InputEvent {
+        loop {
+            let idle_seconds = (Instant::now() - self.last_input).as_secs() as u32;
+            let event = match self.timeouts.next_change(idle_seconds) {
+                Some(at) => {
+                    let timeout = Duration::from_secs((at - idle_seconds) as u64);
+                    with_timeout(timeout, input.receive()).await.ok()
+                }
+                None => Some(input.receive().await),
+            };
+
+            let Some(event) = event else {
+                let idle_seconds = (Instant::now() - self.last_input).as_secs() as u32;
+                self.set_phase(self.timeouts.phase(idle_seconds));
+                continue;
+            };
+
+            self.last_input = event.timestamp;
+            let woken_from = self.phase;
+            self.set_phase(IdlePhase::Active);
+            if woken_from != IdlePhase::Attract {
+                return event;
+            }
+        }
+    }
 }
 
 #[embassy_executor::task]
 pub async fn game_loop(
-    input: &'static Signal<CriticalSectionRawMutex, KeyboardInput>,
-    output: &'static Signal<CriticalSectionRawMutex, GameStage>,
+    input: InputReceiver,
+    output: GameStageSender,
+    settings_output: &'static Signal<CriticalSectionRawMutex, Settings>,
+    idle_output: &'static Signal<CriticalSectionRawMutex, IdlePhase>,
+    idle_timeouts: IdleTimeouts,
+    usb_events: Receiver<'static, CriticalSectionRawMutex, UsbEvent, 4>,
+    keyboard_output: &'static Signal<CriticalSectionRawMutex, bool>,
 ) {
     // Initialize MATLAB code bindings
     initialize();
 
-    // start at board 1 (top left)
-    let mut game_stage = GameStage::InProgress(BoardState::new(), NextUserSelection::SelectCell(1));
-    output.signal(game_stage);
+    let mut settings = Settings::load();
+    let mut stored_settings = settings;
+    settings_output.signal(settings);
+
+    // each game starts with both players choosing their color
+    let mut game_stage = GameStage::ChooseColor(Player::PlayerOne);
+    output.send(game_stage);
+
+    let mut idle_timer = IdleTimer {
+        timeouts: idle_timeouts,
+        last_input: Instant::now(),
+        phase: IdlePhase::Active,
+        output: idle_output,
+    };
+
+    // the stages before the last moves, for undo
+    let mut history = Deque::<GameStage, UNDO_DEPTH>::new();
+    // the game which continues after the settings menu
+    let mut paused_game = None;
+    let mut starting_player = Player::PlayerOne;
+    // when the new game command was pressed the first time
+    let mut new_game_requested: Option<Instant> = None;
+
+    // the connected keyboards, and which one belongs to which player
+    let mut keyboards = Keyboards::new();
+    keyboard_output.signal(false);
 
     loop {
-        let input = input.wait().await;
+        // without a keyboard, the game is paused until one is plugged in
+        let event = if !keyboards.any_connected() {
+            Either::Second(usb_events.receive().await)
+        } else {
+            select(idle_timer.next_input(&input), usb_events.receive()).await
+        };
+        let InputEvent {
+            input,
+            device: keyboard,
+            timestamp,
+        } = match event {
+            Either::First(input) => input,
+            Either::Second(event) => {
+                let connected_before = keyboards.any_connected();
+                match event {
+                    UsbEvent::Mounted {
+                        dev_addr,
+                        vid,
+                        pid,
+                        input_device: true,
+                    } => keyboards.mounted(KeyboardId { dev_addr, vid, pid }),
+                    UsbEvent::Mounted { .. } => {}
+                    UsbEvent::Unmounted { dev_addr } => keyboards.unmounted(dev_addr),
+                }
+                let connected = keyboards.any_connected();
+                if connected_before != connected {
+                    println!("Keyboard connected: {}", connected);
+                    keyboard_output.signal(connected);
+                    // don't act on keys pressed before the pause
+                    input_queue::clear();
+                    idle_timer.reset();
+                }
+                continue;
+            }
+        };
+
+        if input.is_command() {
+            let game_running = paused_game.is_some()
+                || matches!(
+                    game_stage,
+                    GameStage::InProgress(_, _) | GameStage::IllegalMove(_, _, _)
+                );
+            match input {
+                Input::NewGame => {
+                    // one press could be an accident, a running game is only aborted by the second
+                    let confirmed = new_game_requested
+                        .is_some_and(|first| timestamp - first < NEW_GAME_CONFIRMATION);
+                    if game_running && !confirmed {
+                        println!("Press again to abort the game");
+                        new_game_requested = Some(timestamp);
+                        continue;
+                    }
+                    new_game_requested = None;
+                    history.clear();
+                    paused_game = None;
+                    keyboards.unbind_all();
+                    game_stage = GameStage::ChooseColor(Player::PlayerOne);
+                }
+                Input::Undo if !matches!(game_stage, GameStage::ChooseColor(_)) => {
+                    let Some(previous) = history.pop_back() else {
+                        continue;
+                    };
+                    println!("Undo");
+                    game_stage = previous;
+                }
+                Input::Menu if !matches!(game_stage, GameStage::ChooseColor(_)) => {
+                    if game_running {
+                        paused_game = Some(game_stage);
+                    }
+                    game_stage = GameStage::ChooseColor(Player::PlayerOne);
+                }
+                Input::SwapStarter => {
+                    starting_player = starting_player.other();
+                    println!("{:?} starts", starting_player);
+                    // before the first move, the running game starts with the other player too
+                    let GameStage::InProgress(mut state, selection) = game_stage else {
+                        continue;
+                    };
+                    if state.last_moves[0].is_some() {
+                        continue;
+                    }
+                    state.current_player = starting_player;
+                    game_stage = GameStage::InProgress(state, selection);
+                }
+                _ => continue,
+            }
+            output.send(game_stage);
+            continue;
+        }
+
+        // with two keyboards, each player can only use their own
+        let player = match game_stage {
+            GameStage::ChooseColor(player) => Some(player),
+            GameStage::InProgress(state, _) | GameStage::IllegalMove(state, _, _) => {
+                Some(state.current_player)
+            }
+            GameStage::Won(_, _) | GameStage::Draw(_) => None,
+        };
+        if player.is_some_and(|player| !keyboards.may_play(player, keyboard)) {
+            println!("Ignored {:?} from the other player's keyboard", input);
+            continue;
+        }
 
         match &game_stage {
+            GameStage::ChooseColor(player) => {
+                let done = if input == Input::ToggleZoom {
+                    settings.zoom = !settings.zoom;
+                    println!("Zoomed view: {}", settings.zoom);
+                    false
+                } else {
+                    choose_color(&mut settings.colors, *player, input)
+                };
+                settings_output.signal(settings);
+
+                if done {
+                    // pairing: the keyboard which confirmed the color belongs to this player
+                    keyboards.bind(*player, keyboard);
+                    game_stage = match player {
+                        Player::PlayerOne => GameStage::ChooseColor(Player::PlayerTwo),
+                        Player::PlayerTwo => {
+                            if settings != stored_settings {
+                                settings.save();
+                                stored_settings = settings;
+                            }
+                            match paused_game.take() {
+                                // back from the settings menu
+                                Some(paused) => paused,
+                                // start at board 1 (top left)
+                                None => GameStage::InProgress(
+                                    BoardState {
+                                        current_player: starting_player,
+                                        ..BoardState::new()
+                                    },
+                                    NextUserSelection::SelectCell(1, None),
+                                ),
+                            }
+                        }
+                    };
+                    output.send(game_stage);
+                }
+            }
             GameStage::Won(_, _) | GameStage::Draw(_) => {
                 // after a game, wait for enter to create a new game
-                if input == KeyboardInput::Enter {
-                    game_stage =
-                        GameStage::InProgress(BoardState::new(), NextUserSelection::SelectCell(1));
-                    output.signal(game_stage);
+                if input == Input::Confirm {
+                    // the keyboards are paired again for the next game
+                    keyboards.unbind_all();
+                    history.clear();
+                    game_stage = GameStage::ChooseColor(Player::PlayerOne);
+                    output.send(game_stage);
                 }
                 continue;
             }
             GameStage::InProgress(board_state, selection)
             | GameStage::IllegalMove(board_state, selection, _) => {
                 match input {
-                    KeyboardInput::Numpad(n) if (1..=9).contains(&n) => {
-                        // Map numpad numbering to row-major 1..9 ordering used in MATLAB
-                        let mapped = match n {
-                            1 => 7u8,
-                            2 => 8u8,
-                            3 => 9u8,
-                            4 => 4u8,
-                            5 => 5u8,
-                            6 => 6u8,
-                            7 => 1u8,
-                            8 => 2u8,
-                            9 => 3u8,
-                            _ => unreachable!(),
-                        };
+                    Input::Numpad(n) if (1..=9).contains(&n) => {
+                        let mapped = numpad_to_row_major(n);
 
                         match selection {
                             NextUserSelection::SelectGrid => {
                                 // first press selects the mini-grid (1..9)
                                 game_stage = GameStage::InProgress(
                                     *board_state,
-                                    NextUserSelection::SelectCell(mapped),
+                                    NextUserSelection::SelectCell(mapped, None),
                                 );
-                                output.signal(game_stage);
+                                output.send(game_stage);
                             }
-                            NextUserSelection::SelectCell(grid) => {
+                            NextUserSelection::SelectCell(grid, _) => {
                                 // second press selects cell within mini-grid
                                 let cell = mapped;
 
                                 // perform move: grid and cell are both 1..9
+                                let before = GameStage::InProgress(*board_state, *selection);
                                 game_stage = board_state.make_move(*grid, cell);
-                                output.signal(game_stage);
+                                remember_move(&mut history, before, &game_stage);
+                                output.send(game_stage);
                             }
                         }
                     }
+                    Input::Up | Input::Down | Input::Left | Input::Right => {
+                        // the cursor previews where a move would send the opponent
+                        if let (NextUserSelection::SelectCell(grid, focus), Some(direction)) =
+                            (selection, input.direction())
+                        {
+                            game_stage = GameStage::InProgress(
+                                *board_state,
+                                NextUserSelection::SelectCell(
+                                    *grid,
+                                    Some(move_focus(*focus, direction)),
+                                ),
+                            );
+                            output.send(game_stage);
+                        }
+                    }
+                    Input::Confirm => {
+                        // play the focused cell
+                        if let NextUserSelection::SelectCell(grid, Some(cell)) = selection {
+                            let before = GameStage::InProgress(*board_state, *selection);
+                            game_stage = board_state.make_move(*grid, *cell);
+                            remember_move(&mut history, before, &game_stage);
+                            output.send(game_stage);
+                        }
+                    }
+                    Input::Cancel => {
+                        if let NextUserSelection::SelectCell(grid, Some(_)) = selection {
+                            game_stage = GameStage::InProgress(
+                                *board_state,
+                                NextUserSelection::SelectCell(*grid, None),
+                            );
+                            output.send(game_stage);
+                        }
+                    }
                     _ => continue, // Ignore other keys
                 }
             }
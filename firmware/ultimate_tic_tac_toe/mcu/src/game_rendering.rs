@@ -1,315 +1,221 @@
-use alloc::boxed::Box;
+use core::sync::atomic::{AtomicU32, Ordering};
+
 use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
-use embassy_time::Duration;
+use embassy_time::{Duration, Instant, Timer};
 use esp_println::println;
+use game_core::{
+    Frame, STATUS_STRIP_LENGTH, StatusStrip,
+    idle::{IdlePhase, dim, game_brightness, render_idle},
+    rendering::{render_boot_animation, render_frame, render_keyboard_icon, render_status_strip},
+    settings::Settings,
+    status_led::{SystemState, render_status_led},
+    triple_buffer::Writer,
+    zoom::render_with_zoom,
+};
 use smart_leds::RGB8;
 
-use crate::{
-    MATRIX_WIDTH,
-    game::{GameStage, Player},
+use crate::{game::GameStageReceiver, system_status};
+
+// 60 Hz refresh rate, updating the display over SPI takes around 10 ms anyway
+const FRAME_PERIOD: Duration = Duration::from_micros(1_000_000 / 60);
+
+/// Counters for the frame pipeline between the render task and the neopixel task
+pub struct FrameMetrics {
+    /// frames handed to the neopixel task
+    pub published: AtomicU32,
+    /// frames not handed over, because they were identical to the previous one
+    pub skipped: AtomicU32,
+    /// frames overwritten before the neopixel task took them
+    pub dropped: AtomicU32,
+    /// frames which were rendered after their deadline had already passed
+    pub late: AtomicU32,
+    /// frames written to the LEDs
+    pub written: AtomicU32,
+}
+
+pub static FRAME_METRICS: FrameMetrics = FrameMetrics {
+    published: AtomicU32::new(0),
+    skipped: AtomicU32::new(0),
+    dropped: AtomicU32::new(0),
+    late: AtomicU32::new(0),
+    written: AtomicU32::new(0),
 };
 
-/// Convert from x,y coordinates to the linear NeoPixel index
-/// The XY coordinates are 0-indexed, with (0,0) at the top-left
-/// x goes right, y goes down
-fn xy<T>(arr: &mut [T], x: usize, y: usize) -> &mut T {
-    // the strip starts at top left, goes down, then one right and up, one right and down, ...
-    // so even columns go down, odd columns go up.
-    let index = if x % 2 == 0 {
-        // Even columns go down
-        (x * MATRIX_WIDTH) + y
-    } else {
-        // Odd columns go up
-        (x * MATRIX_WIDTH) + (MATRIX_WIDTH - 1 - y)
-    };
-    &mut arr[index]
+/// Everything shown on the LEDs at one point in time
+#[derive(Clone, Copy, PartialEq)]
+pub struct LedFrame {
+    pub matrix: Frame,
+    pub status: StatusStrip,
+    /// the LED on the dev board
+    pub onboard: RGB8,
 }
 
-#[cfg(test)]
-mod test_xy {
-    use super::xy;
+/// seconds since `since`, as used by the rendering functions
+fn seconds_since(since: Instant) -> f32 {
+    (Instant::now() - since).as_millis() as f32 / 1000.0
+}
 
-    #[test]
-    fn test_xy_function() {
-        let mut arr = [0; 256];
-        assert!(xy(&mut arr, 0, 0) == &mut arr[0]);
-        assert!(xy(&mut arr, 0, 1) == &mut arr[1]);
-        assert!(xy(&mut arr, 0, 15) == &mut arr[15]);
-        assert!(xy(&mut arr, 1, 15) == &mut arr[16]);
-        assert!(xy(&mut arr, 1, 0) == &mut arr[31]);
-    }
+/// The state shown by the onboard LED, `since_keyboard_mounted` is None without a keyboard
+fn system_state(since_keyboard_mounted: Option<f32>) -> SystemState {
+    SystemState::new(
+        system_status::FATAL_ERROR.load(Ordering::Relaxed),
+        system_status::last_error().map(seconds_since),
+        system_status::USB_READY.load(Ordering::Relaxed),
+        since_keyboard_mounted,
+    )
 }
 
-// Player colors
-const PLAYER_1_COLOR: RGB8 = RGB8::new(0, 80, 0);
-const PLAYER_2_COLOR: RGB8 = RGB8::new(0, 0, 80);
+/// Hands rendered frames to the neopixel task, skipping frames which didn't change
+struct FrameSender {
+    writer: Writer<'static, LedFrame>,
+    frame_ready: &'static Signal<CriticalSectionRawMutex, ()>,
+    last_frame: Option<LedFrame>,
+}
 
-const ERROR_GLOW: RGB8 = RGB8::new(10, 0, 0); // red glow for illegal move
+impl FrameSender {
+    fn send(&mut self, frame: &LedFrame) {
+        if self.last_frame.as_ref() == Some(frame) {
+            FRAME_METRICS.skipped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        self.last_frame = Some(*frame);
+
+        *self.writer.back() = *frame;
+        if !self.writer.publish() {
+            FRAME_METRICS.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        FRAME_METRICS.published.fetch_add(1, Ordering::Relaxed);
+        self.frame_ready.signal(());
+    }
+}
 
-const CURRENT_GRID_GLOW: RGB8 = RGB8::new(5, 0, 5); // glow for current grid selection
+/// Wait until `deadline`, then move it to the next frame.
+/// If the deadline has already passed, the frame is late and the schedule restarts from now.
+async fn next_frame(deadline: &mut Instant) {
+    if Instant::now() > *deadline {
+        FRAME_METRICS.late.fetch_add(1, Ordering::Relaxed);
+        *deadline = Instant::now();
+    } else {
+        Timer::at(*deadline).await;
+    }
+    *deadline += FRAME_PERIOD;
+}
 
 #[embassy_executor::task]
 pub async fn render_task(
-    input_signal: &'static Signal<CriticalSectionRawMutex, GameStage>,
-    output_signal: &'static Signal<CriticalSectionRawMutex, Box<[RGB8]>>,
+    mut game_stages: GameStageReceiver,
+    frame_writer: Writer<'static, LedFrame>,
+    frame_ready: &'static Signal<CriticalSectionRawMutex, ()>,
+    settings_signal: &'static Signal<CriticalSectionRawMutex, Settings>,
+    idle_signal: &'static Signal<CriticalSectionRawMutex, IdlePhase>,
+    keyboard_signal: &'static Signal<CriticalSectionRawMutex, bool>,
 ) -> ! {
     println!("Render task started");
 
-    // 60 Hz refresh rate, updating the display over SPI takes around 10 ms anyway
-    let mut ticker = embassy_time::Ticker::every(Duration::from_millis(1000 / 60));
+    let mut sender = FrameSender {
+        writer: frame_writer,
+        frame_ready,
+        last_frame: None,
+    };
+    let mut deadline = Instant::now();
 
     // wait for the inital value, until then, render a spinner
-    {
-        let mut i = 0;
-
-        while !input_signal.signaled() {
-            // Demo: Three sine waves cycling through the 16x16 matrix
-            // Red starts at 0, Blue at 1/3, Green at 2/3 of the cycle
-            let mut colors = [RGB8::new(0, 0, 0); 256];
-
-            let time_offset = (i as f32) * 0.1; // Animation speed
-
-            for led_index in 0..256 {
-                let position = (led_index as f32) / 256.0 * 2.0 * core::f32::consts::PI;
-
-                // Three sine waves offset by 2π/3 (120 degrees)
-                let red_phase = position + time_offset;
-                let blue_phase = position + time_offset + 2.0 * core::f32::consts::PI / 3.0;
-                let green_phase = position + time_offset + 4.0 * core::f32::consts::PI / 3.0;
-
-                // Calculate sine values and convert to 0-255 range
-                let red = ((libm::sinf(red_phase)) * 255.0) as u8;
-                let green = ((libm::sinf(green_phase)) * 255.0) as u8;
-                let blue = ((libm::sinf(blue_phase)) * 255.0) as u8;
-
-                colors[led_index] = RGB8::new(red, green, blue);
-            }
-
-            output_signal.signal(Box::new(colors));
-
-            ticker.next().await;
-            i += 1;
+    let boot = Instant::now();
+    let mut game_stage = loop {
+        if let Some(game_stage) = game_stages.try_changed() {
+            break game_stage;
         }
-    }
+        sender.send(&LedFrame {
+            matrix: render_boot_animation(seconds_since(boot)),
+            status: [Default::default(); STATUS_STRIP_LENGTH],
+            onboard: render_status_led(
+                system_state(None),
+                None,
+                &Default::default(),
+                seconds_since(boot),
+            ),
+        });
+        next_frame(&mut deadline).await;
+    };
 
-    let mut game_stage: GameStage;
-    game_stage = input_signal.wait().await;
-    let mut last_changed = embassy_time::Instant::now();
+    // the zoomed view animates the transition from the previous stage
+    let mut previous_stage = game_stage;
+    let mut last_changed = Instant::now();
+    let mut settings = Settings::default();
+    let mut idle_phase = IdlePhase::Active;
+    let mut idle_changed = Instant::now();
+    let mut keyboard_connected = false;
+    let mut keyboard_changed = Instant::now();
 
     loop {
-        let board_state = match &game_stage {
-            GameStage::InProgress(state, _)
-            | GameStage::Won(_, state)
-            | GameStage::Draw(state)
-            | GameStage::IllegalMove(state, _, _) => *state,
-        };
-
-        let mut colors = [RGB8::new(0, 0, 0); 256];
-
-        // render board state
-
-        /// 0 based indizes
-        let cell_offset = |board_idx: usize, cell_idx: usize| -> (usize, usize) {
-            let board_column = board_idx % 3;
-            let board_row = board_idx / 3;
-
-            let x_grid = 1 + board_column * 5;
-            let y_grid = 1 + board_row * 5;
-
-            let cell_x = cell_idx % 3;
-            let cell_y = cell_idx / 3;
-
-            let x = x_grid + cell_x;
-            let y = y_grid + cell_y;
-            (x, y)
-        };
-
-        // Compute selection glow and selection state
-        let selection = match game_stage {
-            GameStage::InProgress(_, sel) => Some(sel),
-            GameStage::IllegalMove(_, sel, _) => Some(sel),
-            _ => None,
-        };
-
-        // Draw occupied cells (one pixel per cell)
-        for i_board in 0..9 {
-            for i_cell in 0..9 {
-                if let Some(player) = board_state.board[i_board][i_cell] {
-                    let (x, y) = cell_offset(i_board, i_cell);
-
-                    *xy(&mut colors, x, y) = match player {
-                        Player::PlayerOne => PLAYER_1_COLOR,
-                        Player::PlayerTwo => PLAYER_2_COLOR,
-                    };
-                }
-            }
+        if let Some(new_settings) = settings_signal.try_take() {
+            settings = new_settings;
         }
-
-        let board_glow_amount: u8 = 10;
-        for i_board in 0..9 {
-            if let Some(finished) = board_state.finished_grids[i_board] {
-                // compute winner color or white for draw
-                let winner_color = match finished {
-                    crate::game::PlayerOrDraw::Player(p) => match p {
-                        Player::PlayerOne => PLAYER_1_COLOR,
-                        Player::PlayerTwo => PLAYER_2_COLOR,
-                    },
-                    crate::game::PlayerOrDraw::Draw => RGB8::new(255, 255, 255),
-                };
-
-                let glow_color = RGB8::new(
-                    (winner_color.r as u16 * board_glow_amount as u16 / 255) as u8,
-                    (winner_color.g as u16 * board_glow_amount as u16 / 255) as u8,
-                    (winner_color.b as u16 * board_glow_amount as u16 / 255) as u8,
-                );
-
-                for i_cell in 0..9 {
-                    if board_state.board[i_board][i_cell].is_none() {
-                        let (x, y) = cell_offset(i_board, i_cell);
-
-                        let pixel = xy(&mut colors, x, y);
-                        // add glow by mixing a small amount of glow_color into the pixel
-                        *pixel = glow_color;
-                    }
-                }
-
-                let (left, top) = cell_offset(i_board, 0);
-                let (right, bottom) = cell_offset(i_board, 8);
-
-                for x in left - 1..right + 2 {
-                    *xy(&mut colors, x, top - 1) = glow_color;
-                    *xy(&mut colors, x, bottom + 1) = glow_color;
-                }
-
-                for y in top - 1..bottom + 2 {
-                    *xy(&mut colors, left - 1, y) = glow_color;
-                    *xy(&mut colors, right + 1, y) = glow_color;
-                }
-            }
+        if let Some(new_phase) = idle_signal.try_take() {
+            idle_phase = new_phase;
+            idle_changed = Instant::now();
         }
-
-        let glow_pulse: RGB8 = {
-            let elapsed = (embassy_time::Instant::now() - last_changed).as_millis() as f32 / 1000.0;
-            let omega = 2.0 * core::f32::consts::PI * 1.0; // 1 Hz pulse
-            let env = (1.0 + libm::cosf(omega * elapsed)) * 0.5;
-            RGB8::new(
-                (CURRENT_GRID_GLOW.r as f32 * env) as u8,
-                (CURRENT_GRID_GLOW.g as f32 * env) as u8,
-                (CURRENT_GRID_GLOW.b as f32 * env) as u8,
-            )
-        };
-
-        // Apply selection glow: if SelectGrid => all empty cells glow; if SelectCell(grid)
-        // => only empty cells inside that big-grid glow. Do not glow border pixels.
-        if let Some(sel) = selection {
-            match sel {
-                crate::game::NextUserSelection::SelectGrid => {
-                    for i_board in 0..9 {
-                        if board_state.finished_grids[i_board].is_some() {
-                            // skip finished big-grids
-                            continue;
-                        }
-                        for i_cell in 0..9 {
-                            if board_state.board[i_board][i_cell].is_none() {
-                                let (x, y) = cell_offset(i_board, i_cell);
-
-                                let pixel = xy(&mut colors, x, y);
-                                *pixel = RGB8::new(
-                                    pixel.r.saturating_add(glow_pulse.r),
-                                    pixel.g.saturating_add(glow_pulse.g),
-                                    pixel.b.saturating_add(glow_pulse.b),
-                                );
-                            }
-                        }
-                    }
-                }
-                crate::game::NextUserSelection::SelectCell(grid) => {
-                    let i_grid: usize = (grid - 1) as usize;
-                    for i_cell in 0..9 {
-                        if board_state.board[i_grid][i_cell].is_none() {
-                            let (x, y) = cell_offset(i_grid, i_cell);
-
-                            let pixel = xy(&mut colors, x, y);
-                            *pixel = RGB8::new(
-                                pixel.r.saturating_add(glow_pulse.r),
-                                pixel.g.saturating_add(glow_pulse.g),
-                                pixel.b.saturating_add(glow_pulse.b),
-                            );
-                        }
-                    }
-                }
-            }
+        if let Some(connected) = keyboard_signal.try_take() {
+            keyboard_connected = connected;
+            keyboard_changed = Instant::now();
         }
+        let color_settings = &settings.colors;
+
+        // frames are rendered on the stack and only copied if they changed,
+        // so a static board doesn't cause any SPI traffic
+        let elapsed = seconds_since(last_changed);
+        let matrix = if !keyboard_connected {
+            render_keyboard_icon(seconds_since(keyboard_changed))
+        } else if settings.zoom {
+            render_with_zoom(&previous_stage, &game_stage, color_settings, elapsed)
+        } else {
+            render_frame(&game_stage, color_settings, elapsed)
+        };
+        let mut status = render_status_strip(&game_stage, color_settings, elapsed);
+
+        // the game keeps running underneath the idle mode, so waking up shows the same board
+        let idle_elapsed = seconds_since(idle_changed);
+        let matrix = render_idle(&matrix, idle_phase, idle_elapsed);
+        dim(&mut status, game_brightness(idle_phase, idle_elapsed));
+
+        let since_keyboard_mounted = keyboard_connected.then(|| seconds_since(keyboard_changed));
+        let state = system_state(since_keyboard_mounted);
+        let led_elapsed = match state {
+            SystemState::KeyboardMounted => seconds_since(keyboard_changed),
+            SystemState::Playing => elapsed,
+            _ => seconds_since(boot),
+        };
+        let onboard = render_status_led(state, Some(&game_stage), color_settings, led_elapsed);
 
-        if let GameStage::IllegalMove(_, _, played_move) = game_stage {
-            let (x, y) = cell_offset(
-                (played_move.grid - 1) as usize,
-                (played_move.cell - 1) as usize,
-            );
-            // compute a pulsing glow effect
-            let pixel = xy(&mut colors, x, y);
-            let elapsed = (embassy_time::Instant::now() - last_changed).as_millis() as f32 / 1000.0;
-            let omega = 2.0 * core::f32::consts::PI * 2.0;
-            let env = (1.0 + libm::cosf(omega * elapsed)) * 0.5;
-            let blend_channel = |a: u8, b: u8, env: f32| -> u8 {
-                let af = (a as f32) * env;
-                let bf = (b as f32) * (1.0 - env);
-                let sum = af + bf;
-                if sum >= 255.0 { 255u8 } else { sum as u8 }
-            };
-            let new_r = blend_channel(ERROR_GLOW.r, pixel.r, env);
-            let new_g = blend_channel(ERROR_GLOW.g, pixel.g, env);
-            let new_b = blend_channel(ERROR_GLOW.b, pixel.b, env);
-
-            *pixel = RGB8::new(new_r, new_g, new_b);
-        }
+        sender.send(&LedFrame {
+            matrix,
+            status,
+            onboard,
+        });
 
-        match game_stage {
-            GameStage::Won(winner, _) => {
-                // flash the winner's color on the border
-                let border_color = match winner {
-                    Player::PlayerOne => PLAYER_1_COLOR,
-                    Player::PlayerTwo => PLAYER_2_COLOR,
-                };
-                for x in 0..16 {
-                    *xy(&mut colors, x, 0) = border_color;
-                    *xy(&mut colors, x, 15) = border_color;
-                }
-                for y in 0..16 {
-                    *xy(&mut colors, 0, y) = border_color;
-                    *xy(&mut colors, 15, y) = border_color;
-                }
-            }
-            GameStage::Draw(_) => {
-                // gray border for draw
-                let border_color = RGB8::new(50, 50, 50);
-                for y in [0, 15] {
-                    for x in 0..16 {
-                        *xy(&mut colors, x, y) = border_color;
-                    }
-                }
-            }
-            GameStage::IllegalMove(_, _, _) | GameStage::InProgress(_, _) => {
-                // highlight current player
-                if board_state.current_player == Player::PlayerOne {
-                    for x in 0..MATRIX_WIDTH {
-                        *xy(&mut colors, x, 0) = PLAYER_1_COLOR;
-                    }
-                } else {
-                    for x in 0..MATRIX_WIDTH {
-                        *xy(&mut colors, x, 15) = PLAYER_2_COLOR;
-                    }
-                }
-            }
+        next_frame(&mut deadline).await;
+        if let Some(new_data) = game_stages.try_changed() {
+            previous_stage = game_stage;
+            game_stage = new_data;
+            last_changed = Instant::now();
         }
+    }
+}
 
-        // done rendering, push it out
-        output_signal.signal(Box::new(colors));
-
+#[embassy_executor::task]
+pub async fn print_frame_metrics_task() {
+    let mut ticker = embassy_time::Ticker::every(Duration::from_secs(10));
+    loop {
         ticker.next().await;
-        if let Some(new_data) = input_signal.try_take() {
-            game_stage = new_data;
-            last_changed = embassy_time::Instant::now();
-        }
+
+        let take = |counter: &AtomicU32| counter.swap(0, Ordering::Relaxed);
+        println!(
+            "Frames/10s: published {}, skipped {}, dropped {}, late {}, written {}",
+            take(&FRAME_METRICS.published),
+            take(&FRAME_METRICS.skipped),
+            take(&FRAME_METRICS.dropped),
+            take(&FRAME_METRICS.late),
+            take(&FRAME_METRICS.written),
+        );
     }
 }
@@ -0,0 +1,102 @@
+//! Decodes the reports of HID gamepads and joysticks into press and release events of their
+//! controls.
+//!
+//! Gamepads have no boot layout, so only interfaces whose report descriptor describes a
+//! gamepad or joystick with buttons are used. The events go through the same auto-repeat and
+//! debounce as the keys (see `key_repeat`), which also maps them to inputs of the game.
+
+use core::cell::RefCell;
+
+use critical_section::Mutex;
+use esp_println::println;
+use game_core::{
+    gamepad::{GamepadEvent, GamepadState},
+    hid_descriptor::ReportDescriptor,
+};
+use heapless::{LinearMap, Vec};
+
+/// HID gamepad interfaces across all devices, one per player and a spare
+pub const MAX_GAMEPADS: usize = 4;
+
+struct Gamepad {
+    descriptor: ReportDescriptor,
+    state: GamepadState,
+}
+
+/// Gamepad interfaces by device address and HID instance
+static GAMEPADS: Mutex<RefCell<LinearMap<(u8, u8), Gamepad, MAX_GAMEPADS>>> =
+    Mutex::new(RefCell::new(LinearMap::new()));
+
+/// A HID interface was mounted, `report_descriptor` is the raw report descriptor sent by the
+/// device. Interfaces which aren't gamepads are ignored.
+pub fn interface_mounted(dev_addr: u8, instance: u8, report_descriptor: &[u8]) {
+    let Ok(descriptor) = ReportDescriptor::parse(report_descriptor) else {
+        // already reported by `hid_keyboard`
+        return;
+    };
+    if !descriptor.is_gamepad() {
+        return;
+    }
+    println!("Device {} instance {}: gamepad", dev_addr, instance);
+
+    critical_section::with(|cs| {
+        let gamepad = Gamepad {
+            descriptor,
+            state: GamepadState::new(),
+        };
+        if GAMEPADS
+            .borrow_ref_mut(cs)
+            .insert((dev_addr, instance), gamepad)
+            .is_err()
+        {
+            println!("Too many gamepads, device {} is ignored", dev_addr);
+        }
+    });
+}
+
+/// True if the device has at least one gamepad interface
+pub fn has_gamepad(dev_addr: u8) -> bool {
+    critical_section::with(|cs| {
+        GAMEPADS
+            .borrow_ref(cs)
+            .keys()
+            .any(|&(addr, _)| addr == dev_addr)
+    })
+}
+
+/// A device was removed, the next one with this address starts with nothing held
+pub fn device_unmounted(dev_addr: u8) {
+    critical_section::with(|cs| {
+        let mut gamepads = GAMEPADS.borrow_ref_mut(cs);
+        while let Some(&key) = gamepads.keys().find(|&&(addr, _)| addr == dev_addr) {
+            gamepads.remove(&key);
+        }
+    });
+}
+
+/// Decode an input report and call `on_event` for every control which changed
+pub fn handle_report(
+    dev_addr: u8,
+    instance: u8,
+    report: &[u8],
+    mut on_event: impl FnMut(GamepadEvent),
+) {
+    // at most one event per control, passed on outside of the critical section
+    let mut events = Vec::<GamepadEvent, 32>::new();
+    critical_section::with(|cs| {
+        let mut gamepads = GAMEPADS.borrow_ref_mut(cs);
+        let Some(gamepad) = gamepads.get_mut(&(dev_addr, instance)) else {
+            return;
+        };
+        // e.g. a vendor report of the same interface
+        let Some(controls) = gamepad.descriptor.decode_gamepad(report) else {
+            return;
+        };
+        gamepad.state.update(controls, |event| {
+            let _ = events.push(event);
+        });
+    });
+    for event in events {
+        on_event(event);
+    }
+}
@@ -0,0 +1,240 @@
+//! Decodes the reports of the HID keyboard interfaces into key events.
+//!
+//! The report descriptor of each interface is parsed when it is mounted, so keyboards which
+//! don't use the boot layout (NKRO, report IDs) work too. If the descriptor has no keys, or
+//! couldn't be parsed, a keyboard interface is switched to the boot protocol and its reports
+//! are read in the boot layout.
+//!
+//! The lock LEDs of all keyboards show the same state of the game, see
+//! `game_core::keyboard_leds`.
+
+use core::cell::{RefCell, UnsafeCell};
+
+use critical_section::Mutex;
+use esp_println::println;
+use game_core::{
+    hid_descriptor::ReportDescriptor,
+    keyboard::{KeyEvent, KeyboardReport, KeyboardState},
+    keyboard_leds::ALL_LEDS,
+};
+use heapless::{LinearMap, Vec};
+
+use crate::tinyusb_callbacks;
+
+/// HID interfaces with keys, across all devices. A keypad may have keys on two interfaces.
+const MAX_KEYBOARDS: usize = 8;
+
+struct Keyboard {
+    /// None to read the reports in the boot layout
+    descriptor: Option<ReportDescriptor>,
+    state: KeyboardState,
+    /// report ID of the LED output report, None if the keyboard has no LEDs
+    led_report_id: Option<u8>,
+    /// the LEDs the keyboard shows, None until the first report was sent
+    leds: Option<u8>,
+    /// index of the keyboard's buffer in `LED_REPORTS`
+    led_slot: usize,
+    /// an LED report is being sent, its buffer mustn't change until it completed
+    led_busy: bool,
+    /// the switch to the boot protocol isn't confirmed yet, the reports are ignored until then
+    boot_pending: bool,
+}
+
+/// The LED output report of every keyboard, `[report ID, LEDs]`. tinyusb reads it while the
+/// transfer runs, so a buffer is only written while its keyboard has no transfer running.
+struct LedReports([UnsafeCell<[u8; 2]>; MAX_KEYBOARDS]);
+
+// the buffers are only written within a critical section, by the owner of the slot
+unsafe impl Sync for LedReports {}
+
+static LED_REPORTS: LedReports = LedReports([const { UnsafeCell::new([0; 2]) }; MAX_KEYBOARDS]);
+
+/// Keyboard interfaces by device address and HID instance
+static KEYBOARDS: Mutex<RefCell<LinearMap<(u8, u8), Keyboard, MAX_KEYBOARDS>>> =
+    Mutex::new(RefCell::new(LinearMap::new()));
+
+/// A HID interface was mounted. `is_keyboard` is true if it uses the keyboard protocol,
+/// `report_descriptor` is the raw report descriptor sent by the device.
+pub fn interface_mounted(dev_addr: u8, instance: u8, is_keyboard: bool, report_descriptor: &[u8]) {
+    let descriptor = match ReportDescriptor::parse(report_descriptor) {
+        Ok(descriptor) if descriptor.has_keys() => Some(descriptor),
+        Ok(_) => None,
+        Err(e) => {
+            println!(
+                "Device {} instance {}: bad report descriptor: {:?}",
+                dev_addr, instance, e
+            );
+            None
+        }
+    };
+    let has_descriptor = descriptor.is_some();
+    if !has_descriptor && !is_keyboard {
+        return;
+    }
+    println!(
+        "Device {} instance {}: keyboard, {} layout",
+        dev_addr,
+        instance,
+        if has_descriptor { "described" } else { "boot" }
+    );
+
+    critical_section::with(|cs| {
+        let mut keyboards = KEYBOARDS.borrow_ref_mut(cs);
+        // there are as many LED buffers as keyboards
+        let led_slot = (0..MAX_KEYBOARDS).find(|&slot| {
+            keyboards
+                .iter()
+                .all(|(&key, keyboard)| key == (dev_addr, instance) || keyboard.led_slot != slot)
+        });
+        let Some(led_slot) = led_slot else {
+            println!("Too many keyboards, device {} is ignored", dev_addr);
+            return;
+        };
+        let led_report_id = match &descriptor {
+            Some(descriptor) => descriptor.led_report_id(),
+            // every boot keyboard has the LED output report
+            None => Some(0),
+        };
+        let keyboard = Keyboard {
+            boot_pending: descriptor.is_none(),
+            descriptor,
+            state: KeyboardState::new(),
+            led_report_id,
+            leds: None,
+            led_slot,
+            led_busy: false,
+        };
+        if keyboards.insert((dev_addr, instance), keyboard).is_err() {
+            println!("Too many keyboards, device {} is ignored", dev_addr);
+        }
+    });
+
+    // all interfaces start in the report protocol (see main.rs), whose reports can't be read
+    // without a descriptor
+    if !has_descriptor && !tinyusb_callbacks::set_boot_protocol(dev_addr, instance) {
+        println!(
+            "Device {} instance {}: cannot switch to the boot protocol, ignored",
+            dev_addr, instance
+        );
+        remove(dev_addr, instance);
+    }
+}
+
+/// The protocol of a keyboard interface was set, `boot` is true if it uses the boot protocol
+/// now. An interface which couldn't be switched is ignored.
+pub fn protocol_set(dev_addr: u8, instance: u8, boot: bool) {
+    if boot {
+        critical_section::with(|cs| {
+            if let Some(keyboard) = KEYBOARDS.borrow_ref_mut(cs).get_mut(&(dev_addr, instance)) {
+                keyboard.boot_pending = false;
+            }
+        });
+    } else {
+        println!(
+            "Device {} instance {}: boot protocol refused, ignored",
+            dev_addr, instance
+        );
+        remove(dev_addr, instance);
+    }
+}
+
+fn remove(dev_addr: u8, instance: u8) {
+    critical_section::with(|cs| {
+        KEYBOARDS.borrow_ref_mut(cs).remove(&(dev_addr, instance));
+    });
+}
+
+/// True if the device has at least one keyboard interface
+pub fn has_keyboard(dev_addr: u8) -> bool {
+    critical_section::with(|cs| {
+        KEYBOARDS
+            .borrow_ref(cs)
+            .keys()
+            .any(|&(addr, _)| addr == dev_addr)
+    })
+}
+
+/// A device was removed, the next one with this address starts with no keys held
+pub fn device_unmounted(dev_addr: u8) {
+    critical_section::with(|cs| {
+        let mut keyboards = KEYBOARDS.borrow_ref_mut(cs);
+        while let Some(&key) = keyboards.keys().find(|&&(addr, _)| addr == dev_addr) {
+            keyboards.remove(&key);
+        }
+    });
+}
+
+/// Decode an input report and call `on_event` for every key which changed
+pub fn handle_report(dev_addr: u8, instance: u8, report: &[u8], on_event: impl FnMut(KeyEvent)) {
+    critical_section::with(|cs| {
+        let mut keyboards = KEYBOARDS.borrow_ref_mut(cs);
+        let Some(keyboard) = keyboards.get_mut(&(dev_addr, instance)) else {
+            return;
+        };
+        if keyboard.boot_pending {
+            return;
+        }
+        let decoded = match &keyboard.descriptor {
+            Some(descriptor) => descriptor.decode(report),
+            None => KeyboardReport::from_bytes(report),
+        };
+        // e.g. the media keys of a composite device, or a too short report
+        let Some(decoded) = decoded else {
+            return;
+        };
+        keyboard.state.update(&decoded, on_event);
+    });
+}
+
+/// Show `leds` (bits of the boot keyboard LED report) on every keyboard which doesn't show
+/// them yet. A keyboard which is busy with another transfer gets them on the next call.
+pub fn set_leds(leds: u8) {
+    let leds = leds & ALL_LEDS;
+    // the reports are sent outside of the critical section
+    let outdated: Vec<(u8, u8, u8, &'static [u8]), MAX_KEYBOARDS> = critical_section::with(|cs| {
+        KEYBOARDS
+            .borrow_ref_mut(cs)
+            .iter_mut()
+            .filter(|(_, keyboard)| keyboard.leds != Some(leds) && !keyboard.led_busy)
+            .filter_map(|(&(dev_addr, instance), keyboard)| {
+                let report_id = keyboard.led_report_id?;
+                keyboard.leds = Some(leds);
+                keyboard.led_busy = true;
+                // no transfer reads the buffer, and only this keyboard uses it
+                let buffer: &'static mut [u8; 2] =
+                    unsafe { &mut *LED_REPORTS.0[keyboard.led_slot].get() };
+                *buffer = [report_id, leds];
+                let buffer: &'static [u8; 2] = buffer;
+                // a device with report IDs expects the ID as first byte, without (ID 0) the
+                // report is just the LED byte
+                let report = match report_id {
+                    0 => &buffer[1..],
+                    _ => &buffer[..],
+                };
+                Some((dev_addr, instance, report_id, report))
+            })
+            .collect()
+    });
+
+    for (dev_addr, instance, report_id, report) in outdated {
+        // the buffer stays untouched until `led_report_complete`
+        let sent =
+            unsafe { tinyusb_callbacks::send_output_report(dev_addr, instance, report_id, report) };
+        if !sent {
+            led_report_complete(dev_addr, instance, false);
+        }
+    }
+}
+
+/// The LED report to the interface was sent, or failed and is sent again with the next LED
+/// update. Its buffer can be reused.
+pub fn led_report_complete(dev_addr: u8, instance: u8, ok: bool) {
+    critical_section::with(|cs| {
+        if let Some(keyboard) = KEYBOARDS.borrow_ref_mut(cs).get_mut(&(dev_addr, instance)) {
+            keyboard.led_busy = false;
+            if !ok {
+                keyboard.leds = None;
+            }
+        }
+    });
+}
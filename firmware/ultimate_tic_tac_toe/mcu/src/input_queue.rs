@@ -0,0 +1,67 @@
+//! Queue of the inputs from the keyboards and gamepads to the game loop.
+//!
+//! Unlike a `Signal`, which only holds the latest value, the queue keeps every key until the
+//! game loop takes it, so quickly typed keys (grid, then cell) can't overwrite each other.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex,
+    channel::{Channel, Receiver},
+};
+use embassy_time::Instant;
+use esp_println::println;
+
+use crate::game::Input;
+
+/// Nobody types 16 keys faster than the game loop handles them, a full queue means the game
+/// loop doesn't take keys right now, e.g. while a move is computed
+pub const INPUT_QUEUE_LEN: usize = 16;
+
+/// A key press or gamepad button, from which device and when it was received
+#[derive(Clone, Copy, Debug)]
+pub struct InputEvent {
+    pub input: Input,
+    /// USB address of the keyboard or gamepad
+    pub device: u8,
+    pub timestamp: Instant,
+}
+
+pub type InputReceiver = Receiver<'static, CriticalSectionRawMutex, InputEvent, INPUT_QUEUE_LEN>;
+
+static INPUT_QUEUE: Channel<CriticalSectionRawMutex, InputEvent, INPUT_QUEUE_LEN> = Channel::new();
+
+/// Number of key presses dropped because the queue was full
+static DROPPED_INPUTS: AtomicU32 = AtomicU32::new(0);
+
+/// Queue an input from the keyboard or gamepad with the USB address `device`, received at
+/// `timestamp`. Can be called from any context.
+///
+/// When the queue is full, the new key is dropped and counted: the keys already queued were
+/// pressed first, so they are still handled in the order in which they were typed.
+pub fn push_input(input: Input, device: u8, timestamp: Instant) {
+    let event = InputEvent {
+        input,
+        device,
+        timestamp,
+    };
+    if INPUT_QUEUE.try_send(event).is_err() {
+        let dropped = DROPPED_INPUTS.fetch_add(1, Ordering::Relaxed) + 1;
+        println!("Input queue full, dropped {:?} ({} so far)", input, dropped);
+    }
+}
+
+/// The receiving end for the game loop
+pub fn receiver() -> InputReceiver {
+    INPUT_QUEUE.receiver()
+}
+
+/// Discard all queued key presses
+pub fn clear() {
+    INPUT_QUEUE.clear();
+}
+
+/// Number of key presses dropped since boot
+pub fn dropped_inputs() -> u32 {
+    DROPPED_INPUTS.load(Ordering::Relaxed)
+}
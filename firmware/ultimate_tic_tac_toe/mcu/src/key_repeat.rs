@@ -0,0 +1,180 @@
+//! Turns the key press and release events from the HID callback into the inputs of the game,
+//! looked up in the keymap (see `KEYMAP` in main.rs), with auto-repeat for the arrow keys and
+//! debounce (see `game_core::key_repeat`). The controls of gamepads get the same treatment,
+//! mapped by `GAMEPAD_MAPPING` in main.rs.
+
+use embassy_futures::select::{Either, select};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use embassy_time::{Instant, Timer};
+use esp_println::println;
+use game_core::{
+    gamepad::{GamepadControl, GamepadEvent},
+    key_repeat::{KeyRepeats, RepeatSettings},
+    keyboard::KeyEvent,
+    keymap::{self, KeyBinding},
+};
+use heapless::LinearMap;
+
+use crate::{game::Input, hid_gamepad::MAX_GAMEPADS, input_queue};
+
+#[derive(Clone, Copy, Debug)]
+enum KeyMessage {
+    Key(KeyEvent),
+    Gamepad(GamepadEvent),
+    /// the device was unplugged, the releases of its held keys never arrive
+    Unmounted,
+}
+
+/// Raw key and gamepad events with the USB address of the device and the time they were
+/// received, a press and a release per key
+static KEY_EVENTS: Channel<CriticalSectionRawMutex, (u8, KeyMessage, Instant), 16> = Channel::new();
+
+fn push(dev_addr: u8, message: KeyMessage) {
+    if KEY_EVENTS
+        .try_send((dev_addr, message, Instant::now()))
+        .is_err()
+    {
+        println!("Key event queue full, dropped {:X?}", message);
+    }
+}
+
+/// Pass a key event of the keyboard with `dev_addr` on to the key repeat task, can be called
+/// from any context
+pub fn push_key_event(dev_addr: u8, event: KeyEvent) {
+    push(dev_addr, KeyMessage::Key(event));
+}
+
+/// Pass an event of the gamepad with `dev_addr` on to the key repeat task, can be called from
+/// any context
+pub fn push_gamepad_event(dev_addr: u8, event: GamepadEvent) {
+    push(dev_addr, KeyMessage::Gamepad(event));
+}
+
+/// The device with `dev_addr` was removed, a key it held stops repeating and its modifiers
+/// are forgotten. Can be called from any context.
+pub fn device_unmounted(dev_addr: u8) {
+    push(dev_addr, KeyMessage::Unmounted);
+}
+
+/// Keyboards with their own repeat and modifiers, more are used without repeat
+const MAX_KEYBOARDS: usize = 8;
+
+/// Only the cursor keys repeat, a repeated number or Enter would play a move by accident
+fn repeats(key: Input) -> bool {
+    matches!(key, Input::Up | Input::Down | Input::Left | Input::Right)
+}
+
+/// Queue the repeats which are due at `now`. `repeating` is the key each device pressed last
+/// with its input.
+fn push_repeats<const N: usize>(
+    repeat: &mut KeyRepeats<N>,
+    repeating: &LinearMap<u8, (u8, Input), N>,
+    now: u64,
+) {
+    while let Some((device, key, due)) = repeat.poll(now) {
+        if let Some(&(_, input)) = repeating.get(&device).filter(|(held, _)| *held == key) {
+            input_queue::push_input(input, device, Instant::from_millis(due));
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub async fn key_repeat_task(
+    settings: RepeatSettings,
+    keymap: &'static [(KeyBinding, Input)],
+    gamepad_mapping: &'static [(GamepadControl, Input)],
+) {
+    println!("Key repeat task started");
+    // every keyboard repeats its own key, by its USB address
+    let mut repeat = KeyRepeats::<MAX_KEYBOARDS>::new(settings);
+    // the key each keyboard pressed last and its input, by its USB address
+    let mut repeating = LinearMap::<u8, (u8, Input), MAX_KEYBOARDS>::new();
+    // the modifier byte of each keyboard, by its USB address
+    let mut modifiers = LinearMap::<u8, u8, MAX_KEYBOARDS>::new();
+    // the same for the gamepads, a control is identified by its index in the mapping
+    let mut gamepad_repeat = KeyRepeats::<MAX_GAMEPADS>::new(settings);
+    let mut gamepad_repeating = LinearMap::<u8, (u8, Input), MAX_GAMEPADS>::new();
+
+    loop {
+        let deadline = match (repeat.next_deadline(), gamepad_repeat.next_deadline()) {
+            (Some(key), Some(gamepad)) => Some(key.min(gamepad)),
+            (key, gamepad) => key.or(gamepad),
+        };
+        let event = match deadline {
+            Some(deadline) => {
+                match select(
+                    KEY_EVENTS.receive(),
+                    Timer::at(Instant::from_millis(deadline)),
+                )
+                .await
+                {
+                    Either::First(event) => Some(event),
+                    Either::Second(()) => None,
+                }
+            }
+            None => Some(KEY_EVENTS.receive().await),
+        };
+
+        match event {
+            Some((keyboard, KeyMessage::Key(KeyEvent::Pressed(usage)), at)) => {
+                let held = modifiers.get(&keyboard).copied().unwrap_or(0);
+                let bit = keymap::modifier_bit(usage);
+                if bit != 0 {
+                    // a full map only loses the modifiers of the ninth keyboard
+                    let _ = modifiers.insert(keyboard, held | bit);
+                }
+                // other keys, e.g. modifiers, don't interrupt the repeat of an arrow key
+                if let Some(key) = keymap::lookup(keymap, usage, held) {
+                    if repeat.press(keyboard, usage, repeats(key), at.as_millis()) {
+                        println!("Key pressed: {:?} on keyboard {}", key, keyboard);
+                        let _ = repeating.insert(keyboard, (usage, key));
+                        input_queue::push_input(key, keyboard, at);
+                    }
+                }
+            }
+            Some((keyboard, KeyMessage::Key(KeyEvent::Released(usage)), at)) => {
+                if let Some(held) = modifiers.get_mut(&keyboard) {
+                    *held &= !keymap::modifier_bit(usage);
+                }
+                repeat.release(keyboard, usage, at.as_millis());
+            }
+            Some((gamepad, KeyMessage::Gamepad(GamepadEvent::Pressed(control)), at)) => {
+                // controls without a mapping are ignored
+                let mapped = gamepad_mapping
+                    .iter()
+                    .position(|(mapped, _)| *mapped == control);
+                if let Some(index) = mapped {
+                    let input = gamepad_mapping[index].1;
+                    let index = index as u8;
+                    if gamepad_repeat.press(gamepad, index, repeats(input), at.as_millis()) {
+                        println!("Gamepad {:?}: {:?} on device {}", control, input, gamepad);
+                        let _ = gamepad_repeating.insert(gamepad, (index, input));
+                        input_queue::push_input(input, gamepad, at);
+                    }
+                }
+            }
+            Some((gamepad, KeyMessage::Gamepad(GamepadEvent::Released(control)), at)) => {
+                let mapped = gamepad_mapping
+                    .iter()
+                    .position(|(mapped, _)| *mapped == control);
+                if let Some(index) = mapped {
+                    gamepad_repeat.release(gamepad, index as u8, at.as_millis());
+                }
+            }
+            Some((device, KeyMessage::Unmounted, _)) => {
+                // the next device with this address starts with nothing held, even if a
+                // release was lost
+                repeat.remove(device);
+                repeating.remove(&device);
+                modifiers.remove(&device);
+                gamepad_repeat.remove(device);
+                gamepad_repeating.remove(&device);
+            }
+            None => {}
+        }
+
+        let now = Instant::now().as_millis();
+        push_repeats(&mut repeat, &repeating, now);
+        push_repeats(&mut gamepad_repeat, &gamepad_repeating, now);
+    }
+}
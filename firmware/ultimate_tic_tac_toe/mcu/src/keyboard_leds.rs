@@ -0,0 +1,35 @@
+//! Shows the state of the game on the lock LEDs of the keyboards (see
+//! `game_core::keyboard_leds`), e.g. Num Lock blinks while it's player two's turn.
+//!
+//! The LED reports are tinyusb control transfers, so this task has to run on the same executor
+//! as the USB host loop in `main`.
+
+use embassy_futures::select::{Either, select};
+use embassy_time::{Duration, Instant, Timer};
+use esp_println::println;
+use game_core::keyboard_leds::render_keyboard_leds;
+
+use crate::{game::GameStageReceiver, hid_keyboard};
+
+/// fast enough for the blinking, and to update a keyboard shortly after it was plugged in
+const UPDATE_INTERVAL: Duration = Duration::from_millis(100);
+
+#[embassy_executor::task]
+pub async fn keyboard_leds_task(mut game_stages: GameStageReceiver) {
+    println!("Keyboard LED task started");
+    let mut game_stage = game_stages.get().await;
+    let mut changed_at = Instant::now();
+
+    loop {
+        let elapsed = changed_at.elapsed().as_millis() as f32 / 1000.0;
+        // only keyboards which don't show these LEDs yet get a report
+        hid_keyboard::set_leds(render_keyboard_leds(&game_stage, elapsed));
+
+        if let Either::First(new_stage) =
+            select(game_stages.changed(), Timer::after(UPDATE_INTERVAL)).await
+        {
+            game_stage = new_stage;
+            changed_at = Instant::now();
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use esp_hal::{Blocking, dma::DmaTxBuf, rmt, spi, spi::master::SpiDma};
+use smart_leds::RGB8;
+
+use crate::{led_rmt::RmtLedChain, led_spi::SpiLedChain};
+
+/// Peripheral which generates the WS2812 signal
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LedBackend {
+    /// SPI with DMA, every data bit is encoded as 4 SPI bits
+    Spi,
+    /// RMT, every data bit is one RMT pulse code. The driver blocks the core while it sends
+    /// a frame, see `RmtLedChain::write`.
+    Rmt,
+}
+
+#[derive(Debug)]
+pub enum LedError {
+    Spi(spi::Error),
+    Rmt(rmt::Error),
+    /// the peripheral was lost after an earlier error
+    Unavailable,
+}
+
+/// A chain of WS2812 LEDs, independent of the peripheral driving it
+// only used within the firmware, so the missing Send bound on the future doesn't matter
+#[allow(async_fn_in_trait)]
+pub trait LedChain {
+    async fn write(&mut self, leds: &[RGB8]) -> Result<(), LedError>;
+}
+
+/// The RMT channel used for the matrix, if it is driven by RMT
+pub type MatrixRmtChannel = rmt::Channel<Blocking, 0>;
+/// The RMT channel used for the status strip
+pub type StatusRmtChannel = rmt::Channel<Blocking, 1>;
+/// The RMT channel used for the onboard LED
+pub type OnboardRmtChannel = rmt::Channel<Blocking, 2>;
+
+/// The peripheral for the matrix, as configured in main.rs.
+/// It's only turned into an `LedChain` on the core which drives the LEDs.
+pub enum MatrixLedConfig {
+    Spi(SpiDma<'static, Blocking>, DmaTxBuf),
+    Rmt(MatrixRmtChannel),
+}
+
+pub enum MatrixLeds {
+    Spi(SpiLedChain),
+    Rmt(RmtLedChain<MatrixRmtChannel>),
+}
+
+impl MatrixLeds {
+    pub fn new(config: MatrixLedConfig, led_count: usize) -> Self {
+        match config {
+            MatrixLedConfig::Spi(spi, buffer) => MatrixLeds::Spi(SpiLedChain::new(spi, buffer)),
+            MatrixLedConfig::Rmt(channel) => MatrixLeds::Rmt(RmtLedChain::new(channel, led_count)),
+        }
+    }
+}
+
+impl LedChain for MatrixLeds {
+    async fn write(&mut self, leds: &[RGB8]) -> Result<(), LedError> {
+        match self {
+            MatrixLeds::Spi(chain) => chain.write(leds).await,
+            // blocking, there is no async RMT driver
+            MatrixLeds::Rmt(chain) => chain.write(leds),
+        }
+    }
+}
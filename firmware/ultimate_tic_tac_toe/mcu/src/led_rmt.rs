@@ -0,0 +1,82 @@
+use alloc::{boxed::Box, vec};
+use esp_hal::{
+    gpio::Level,
+    rmt::{PulseCode, TxChannel},
+};
+use smart_leds::RGB8;
+
+use crate::led_chain::LedError;
+
+// Pulse lengths in RMT ticks, with the RMT clocked at 80 MHz and a divider of 1 (12.5 ns/tick)
+const T0H: u16 = 32; // 0.40 us
+const T0L: u16 = 68; // 0.85 us
+const T1H: u16 = 64; // 0.80 us
+const T1L: u16 = 36; // 0.45 us
+
+/// Blocking WS2812 driver on an RMT channel, every data bit is one RMT pulse code.
+///
+/// A frame is much larger than the RMT channel memory, so the transmission uses the blocking
+/// API, which refills the channel memory while the frame is sent out. The async API of the RMT
+/// can't refill it, so there is no async version of this driver.
+/// The line stays low between frames, which latches the data.
+pub struct RmtLedChain<C: TxChannel> {
+    // moved into the transaction while it runs
+    channel: Option<C>,
+    pulses: Box<[u32]>,
+}
+
+impl<C: TxChannel> RmtLedChain<C> {
+    pub fn new(channel: C, led_count: usize) -> Self {
+        RmtLedChain {
+            channel: Some(channel),
+            // 24 bits per LED, plus the end marker
+            pulses: vec![0u32; led_count * 24 + 1].into_boxed_slice(),
+        }
+    }
+}
+
+/// Encode the LEDs into RMT pulse codes, terminated by an end marker.
+/// Returns the number of codes written.
+fn encode(leds: &[RGB8], pulses: &mut [u32]) -> usize {
+    let zero: u32 = PulseCode::new(Level::High, T0H, Level::Low, T0L);
+    let one: u32 = PulseCode::new(Level::High, T1H, Level::Low, T1L);
+
+    let mut i = 0;
+    for led in leds {
+        // the LEDs expect green, red, blue, most significant bit first
+        for byte in [led.g, led.r, led.b] {
+            for bit in (0..8).rev() {
+                pulses[i] = if byte & (1 << bit) != 0 { one } else { zero };
+                i += 1;
+            }
+        }
+    }
+    pulses[i] = PulseCode::empty();
+    i + 1
+}
+
+impl<C: TxChannel> RmtLedChain<C> {
+    /// Send the LEDs and wait until they are out, 30 us per LED: 0.24 ms for the status strip,
+    /// but 7.7 ms for the whole matrix. The executor is blocked meanwhile.
+    pub fn write(&mut self, leds: &[RGB8]) -> Result<(), LedError> {
+        let Some(channel) = self.channel.take() else {
+            // the channel is lost if a transmission couldn't be started
+            return Err(LedError::Unavailable);
+        };
+
+        let len = encode(leds, &mut self.pulses);
+        let transaction = channel
+            .transmit(&self.pulses[..len])
+            .map_err(LedError::Rmt)?;
+        match transaction.wait() {
+            Ok(channel) => {
+                self.channel = Some(channel);
+                Ok(())
+            }
+            Err((e, channel)) => {
+                self.channel = Some(channel);
+                Err(LedError::Rmt(e))
+            }
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use esp_hal::{Async, Blocking, dma::DmaTxBuf, spi::master::SpiDma};
+pub use game_core::ws2812::ENCODED_FRAME_LEN;
+use game_core::ws2812::encode;
+use smart_leds::RGB8;
+
+use crate::led_chain::{LedChain, LedError};
+
+/// Async WS2812 driver on SPI: the frame is encoded directly into the DMA buffer, and the task
+/// yields while the transfer runs, so the core is free for other tasks.
+pub struct SpiLedChain {
+    // both are moved into the transfer while it runs, and given back afterwards
+    spi: Option<SpiDma<'static, Async>>,
+    buffer: Option<DmaTxBuf>,
+}
+
+impl SpiLedChain {
+    /// `buffer` must hold at least `ENCODED_FRAME_LEN` bytes.
+    /// Call this on the core which writes to the chain, the DMA interrupt is handled there.
+    pub fn new(spi: SpiDma<'static, Blocking>, buffer: DmaTxBuf) -> Self {
+        assert!(buffer.capacity() >= ENCODED_FRAME_LEN);
+        SpiLedChain {
+            spi: Some(spi.into_async()),
+            buffer: Some(buffer),
+        }
+    }
+}
+
+impl LedChain for SpiLedChain {
+    async fn write(&mut self, leds: &[RGB8]) -> Result<(), LedError> {
+        let (Some(spi), Some(mut buffer)) = (self.spi.take(), self.buffer.take()) else {
+            // a previous transfer was cancelled while running, the driver is gone
+            return Err(LedError::Unavailable);
+        };
+
+        let len = encode(leds, buffer.as_mut_slice());
+        buffer.set_length(len);
+
+        let mut transfer = match spi.write(len, buffer) {
+            Ok(transfer) => transfer,
+            Err((e, spi, buffer)) => {
+                self.spi = Some(spi);
+                self.buffer = Some(buffer);
+                return Err(LedError::Spi(e));
+            }
+        };
+        transfer.wait_for_done().await;
+        let (spi, buffer) = transfer.wait();
+
+        self.spi = Some(spi);
+        self.buffer = Some(buffer);
+        Ok(())
+    }
+}
@@ -3,19 +3,35 @@
 #![feature(never_type)]
 #![feature(c_variadic)]
 
+mod display;
 mod game;
 mod game_rendering;
+mod hid_gamepad;
+mod hid_keyboard;
+mod input_queue;
+mod key_repeat;
+mod keyboard_leds;
+mod led_chain;
+mod led_rmt;
+mod led_spi;
+mod settings;
+mod system_status;
 mod tinyusb_callbacks;
 
 use embassy_executor::Spawner;
-use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
-use embassy_time::Duration;
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel, signal::Signal,
+};
+use embassy_time::{Duration, with_timeout};
 use esp_backtrace as _;
 use esp_hal::{
     delay::Delay,
-    dma::{DmaRxBuf, DmaTxBuf},
+    dma::DmaTxBuf,
     dma_buffers,
+    gpio::Level,
+    i2c::{self, master::I2c},
     peripherals::Peripherals,
+    rmt::{Rmt, TxChannelConfig, TxChannelCreator},
     system::{CpuControl, Stack},
     time::Rate,
     timer::{AnyTimer, timg::TimerGroup},
@@ -28,17 +44,31 @@ use static_cell::StaticCell;
 use esp_alloc as _;
 
 use crate::{
-    game::{GameStage, KeyboardInput},
-    game_rendering::render_task,
+    display::display_task,
+    game::{GameStageWatch, Input},
+    game_rendering::{FRAME_METRICS, LedFrame, print_frame_metrics_task, render_task},
+    led_chain::{
+        LedBackend, LedChain, MatrixLedConfig, MatrixLeds, OnboardRmtChannel, StatusRmtChannel,
+    },
+    led_rmt::RmtLedChain,
+    tinyusb_callbacks::{HidProtocol, UsbEvent},
+};
+use game_core::{
+    MATRIX_LENGTH, STATUS_STRIP_LENGTH,
+    gamepad::GamepadControl,
+    idle::{IdlePhase, IdleTimeouts},
+    key_repeat::RepeatSettings,
+    keymap::KeyBinding,
+    settings::Settings,
+    triple_buffer::{Reader, TripleBuffer},
 };
 
 extern crate alloc;
 
-use alloc::{boxed::Box, format};
+use alloc::format;
 use core::ptr::addr_of_mut;
 
 use smart_leds::RGB8;
-use smart_leds::SmartLedsWrite;
 
 macro_rules! error_with_location {
     ($msg:expr) => {
@@ -49,15 +79,88 @@ macro_rules! error_with_location {
     };
 }
 
-const MATRIX_WIDTH: usize = 16; // 3x3 grid plus borders
-const MATRIX_HEIGHT: usize = 16; // 3x3 grid plus borders
-const MATRIX_LENGTH: usize = MATRIX_WIDTH * MATRIX_HEIGHT;
-const TOTAL_NEOPIXEL_LENGTH: usize = MATRIX_LENGTH;
+/// Longest time the USB host loop sleeps without an interrupt. `tuh_task_ext` drains the event
+/// queue, so events queued while it runs are handled right away, but tinyusb can also queue
+/// events from thread context (`usbh_defer_func`, or a `tuh_*` call from another task like the
+/// LED reports), which raises no interrupt. Those are handled after at most this long, it costs
+/// 10 wakeups per second while idle.
+const USB_POLL_FALLBACK: Duration = Duration::from_millis(100);
+
+/// Peripheral driving the matrix on GPIO21: SPI2 or RMT channel 0
+const MATRIX_LED_BACKEND: LedBackend = LedBackend::Spi;
+
+/// Seconds without a key press until the matrix dims, and until the attract animation starts.
+/// Set either to None to disable it.
+const IDLE_TIMEOUTS: IdleTimeouts = IdleTimeouts {
+    dim_after: Some(60),
+    attract_after: Some(300),
+};
+
+/// Auto-repeat of the arrow keys and the d-pad, and debounce of all keys and buttons, in
+/// milliseconds.
+/// Set `delay` to None to disable the auto-repeat.
+const KEY_REPEAT: RepeatSettings = RepeatSettings {
+    delay: Some(400),
+    interval: 150,
+    debounce: 20,
+};
 
-type NeopixelT<'a> = ws2812_spi::prerendered::Ws2812<
-    'static,
-    esp_hal::spi::master::SpiDmaBus<'a, esp_hal::Blocking>,
->;
+/// Input of each key by its HID usage, optionally with modifiers (`KeyBinding::ctrl`).
+/// Keys which aren't listed are ignored.
+const KEYMAP: &[(KeyBinding, Input)] = &[
+    // numpad keys for the positions 1-9
+    (KeyBinding::key(0x59), Input::Numpad(1)),
+    (KeyBinding::key(0x5A), Input::Numpad(2)),
+    (KeyBinding::key(0x5B), Input::Numpad(3)),
+    (KeyBinding::key(0x5C), Input::Numpad(4)),
+    (KeyBinding::key(0x5D), Input::Numpad(5)),
+    (KeyBinding::key(0x5E), Input::Numpad(6)),
+    (KeyBinding::key(0x5F), Input::Numpad(7)),
+    (KeyBinding::key(0x60), Input::Numpad(8)),
+    (KeyBinding::key(0x61), Input::Numpad(9)),
+    // arrow keys
+    (KeyBinding::key(0x52), Input::Up),
+    (KeyBinding::key(0x51), Input::Down),
+    (KeyBinding::key(0x50), Input::Left),
+    (KeyBinding::key(0x4F), Input::Right),
+    // Enter and numpad Enter
+    (KeyBinding::key(0x28), Input::Confirm),
+    (KeyBinding::key(0x58), Input::Confirm),
+    // Escape
+    (KeyBinding::key(0x29), Input::Cancel),
+    // Backspace, numpad - and Ctrl+Z
+    (KeyBinding::key(0x2A), Input::Undo),
+    (KeyBinding::key(0x56), Input::Undo),
+    (KeyBinding::ctrl(0x1D), Input::Undo),
+    // numpad /, F1
+    (KeyBinding::key(0x54), Input::Menu),
+    (KeyBinding::key(0x3A), Input::Menu),
+    // numpad *, F2 and Ctrl+N, pressed twice
+    (KeyBinding::key(0x55), Input::NewGame),
+    (KeyBinding::key(0x3B), Input::NewGame),
+    (KeyBinding::ctrl(0x11), Input::NewGame),
+    // numpad +, F3
+    (KeyBinding::key(0x57), Input::SwapStarter),
+    (KeyBinding::key(0x3C), Input::SwapStarter),
+    // settings: numpad 0 zooms, the number keys 0-2 above the letters set the marked moves
+    (KeyBinding::key(0x62), Input::ToggleZoom),
+    (KeyBinding::key(0x27), Input::MarkedMoves(0)),
+    (KeyBinding::key(0x1E), Input::MarkedMoves(1)),
+    (KeyBinding::key(0x1F), Input::MarkedMoves(2)),
+];
+
+/// Input of each gamepad control: the d-pad or the stick moves the cursor, buttons are
+/// numbered as the gamepad reports them. Controls which aren't listed are ignored.
+const GAMEPAD_MAPPING: &[(GamepadControl, Input)] = &[
+    (GamepadControl::Up, Input::Up),
+    (GamepadControl::Down, Input::Down),
+    (GamepadControl::Left, Input::Left),
+    (GamepadControl::Right, Input::Right),
+    (GamepadControl::Button(1), Input::Confirm),
+    (GamepadControl::Button(2), Input::Cancel),
+    (GamepadControl::Button(3), Input::Undo),
+    (GamepadControl::Button(4), Input::Menu),
+];
 
 static mut APP_CORE_STACK: Stack<8192> = Stack::new();
 
@@ -68,10 +171,19 @@ pub async fn print_interrupt_count_task() {
     println!("Starting interrupt count task");
     let mut ticker = embassy_time::Ticker::every(Duration::from_secs(1));
     let mut prev_count = 0;
+    let mut prev_runs = 0;
     loop {
         let count = tinyusb_callbacks::INTERRUPT_COUNTER.load(core::sync::atomic::Ordering::SeqCst);
-        println!("Interrupts: {}/1s", count - prev_count);
+        // with the interrupt-driven host loop, close to the number of interrupts
+        let runs = tinyusb_callbacks::USB_TASK_RUNS.load(core::sync::atomic::Ordering::Relaxed);
+        println!(
+            "Interrupts: {}/1s, USB task runs: {}/1s, dropped keys: {}",
+            count - prev_count,
+            runs - prev_runs,
+            input_queue::dropped_inputs()
+        );
         prev_count = count;
+        prev_runs = runs;
 
         ticker.next().await;
     }
@@ -79,29 +191,36 @@ pub async fn print_interrupt_count_task() {
 
 #[embassy_executor::task]
 async fn neopixel_task(
-    spi: esp_hal::spi::master::SpiDmaBus<'static, esp_hal::Blocking>,
-    update_signal: &'static Signal<CriticalSectionRawMutex, Box<[RGB8]>>,
+    matrix_config: MatrixLedConfig,
+    mut status_strip: RmtLedChain<StatusRmtChannel>,
+    mut onboard_led: RmtLedChain<OnboardRmtChannel>,
+    mut frame_reader: Reader<'static, LedFrame>,
+    frame_ready: &'static Signal<CriticalSectionRawMutex, ()>,
 ) -> ! {
     println!("Neopixel task started");
 
-    let neopixel_buffer = Box::leak(Box::new([0u8; 12 * TOTAL_NEOPIXEL_LENGTH + 140]));
-    let mut neopixel: NeopixelT = ws2812_spi::prerendered::Ws2812::new(spi, neopixel_buffer);
-
-    // // demo
-    // let mut delay = Delay::new();
-    // for i in 0..256 {
-    //     let mut colors = [RGB8::new(0, 0, 0); MATRIX_LENGTH];
-    //     colors[i] = RGB8::new(255, 0, 0);
-    //     if let Err(e) = neopixel.write(colors) {
-    //         println!("Failed to write to NeoPixel: {:?}", e);
-    //     }
-    //     // delay.delay_ms(20);
-    // }
+    // created here, so a DMA interrupt is handled on this core
+    let mut matrix = MatrixLeds::new(matrix_config, MATRIX_LENGTH);
 
     loop {
-        let new_state = update_signal.wait().await;
-        if let Err(e) = neopixel.write(new_state) {
-            println!("Failed to write to NeoPixel: {:?}", e);
+        frame_ready.wait().await;
+        if let Some(frame) = frame_reader.take() {
+            if let Err(e) = matrix.write(&frame.matrix).await {
+                println!("Failed to write to NeoPixel: {:?}", e);
+                system_status::report_error();
+            }
+            // the small chains are on the blocking RMT driver, they take ~0.3 ms together
+            if let Err(e) = status_strip.write(&frame.status) {
+                println!("Failed to write to status strip: {:?}", e);
+                system_status::report_error();
+            }
+            if let Err(e) = onboard_led.write(core::slice::from_ref(&frame.onboard)) {
+                println!("Failed to write to onboard LED: {:?}", e);
+                system_status::report_error();
+            }
+            FRAME_METRICS
+                .written
+                .fetch_add(1, core::sync::atomic::Ordering::Relaxed);
         }
     }
 }
@@ -144,25 +263,67 @@ async fn _main(spawner: Spawner) -> Result<!> {
     // Setup SPI for NeoPixel
     println!("Setting up NeoPixel...");
 
-    // Set up DMA for SPI
-    let (rx_buffer, rx_descriptors, tx_buffer, tx_descriptors) = dma_buffers!(1, 4 * 1024);
-    let dma_rx_buf = DmaRxBuf::new(rx_descriptors, rx_buffer)
-        .map_err(|err| error_with_location!("Failed to create DMA RX buffer: {:?}", err))?;
-    let dma_tx_buf = DmaTxBuf::new(tx_descriptors, tx_buffer)
-        .map_err(|err| error_with_location!("Failed to create DMA TX buffer: {:?}", err))?;
-
-    let spi: esp_hal::spi::master::SpiDmaBus<'_, esp_hal::Blocking> =
-        esp_hal::spi::master::Spi::new(
-            peripherals.SPI2,
-            esp_hal::spi::master::Config::default().with_frequency(Rate::from_khz(4_500)),
-        )?
-        .with_mosi(peripherals.GPIO21)
-        .with_dma(peripherals.DMA_CH1)
-        .with_buffers(dma_rx_buf, dma_tx_buf);
-
-    static NEOPIXEL_SIGNAL: StaticCell<Signal<CriticalSectionRawMutex, Box<[RGB8]>>> =
-        StaticCell::new();
-    let neopixel_signal = &*NEOPIXEL_SIGNAL.init(Signal::new());
+    // RMT with 80 MHz and a divider of 1, see led_rmt.rs for the pulse lengths
+    let rmt = Rmt::new(peripherals.RMT, Rate::from_mhz(80))
+        .map_err(|err| error_with_location!("Failed to initialize RMT: {:?}", err))?;
+    let rmt_config = TxChannelConfig::default()
+        .with_clk_divider(1)
+        .with_idle_output_level(Level::Low)
+        .with_idle_output(true)
+        .with_carrier_modulation(false);
+
+    let matrix_config = match MATRIX_LED_BACKEND {
+        LedBackend::Spi => {
+            // Set up DMA for SPI
+            // only the TX direction is used, the frame is encoded directly into the TX buffer
+            let (_, _, tx_buffer, tx_descriptors) = dma_buffers!(1, led_spi::ENCODED_FRAME_LEN);
+            let dma_tx_buf = DmaTxBuf::new(tx_descriptors, tx_buffer)
+                .map_err(|err| error_with_location!("Failed to create DMA TX buffer: {:?}", err))?;
+
+            let spi = esp_hal::spi::master::Spi::new(
+                peripherals.SPI2,
+                esp_hal::spi::master::Config::default().with_frequency(Rate::from_khz(4_500)),
+            )?
+            .with_mosi(peripherals.GPIO21)
+            .with_dma(peripherals.DMA_CH1);
+            MatrixLedConfig::Spi(spi, dma_tx_buf)
+        }
+        LedBackend::Rmt => {
+            let channel = rmt
+                .channel0
+                .configure_tx(peripherals.GPIO21, rmt_config)
+                .map_err(|err| error_with_location!("Failed to configure RMT: {:?}", err))?;
+            MatrixLedConfig::Rmt(channel)
+        }
+    };
+
+    // second chain: status strip next to the matrix, always on RMT
+    let status_channel = rmt
+        .channel1
+        .configure_tx(peripherals.GPIO47, rmt_config)
+        .map_err(|err| error_with_location!("Failed to configure RMT: {:?}", err))?;
+    let status_strip = RmtLedChain::new(status_channel, STATUS_STRIP_LENGTH);
+
+    // third chain: the single LED on the dev board, shows the state of the system
+    let onboard_channel = rmt
+        .channel2
+        .configure_tx(peripherals.GPIO48, rmt_config)
+        .map_err(|err| error_with_location!("Failed to configure RMT: {:?}", err))?;
+    let onboard_led = RmtLedChain::new(onboard_channel, 1);
+
+    // frames are exchanged through a triple buffer, so neither core waits for the other
+    // and nothing is allocated per frame
+    static FRAME_BUFFER: StaticCell<TripleBuffer<LedFrame>> = StaticCell::new();
+    let (frame_writer, frame_reader) = FRAME_BUFFER
+        .init(TripleBuffer::new(LedFrame {
+            matrix: [RGB8::default(); MATRIX_LENGTH],
+            status: [RGB8::default(); STATUS_STRIP_LENGTH],
+            onboard: RGB8::default(),
+        }))
+        .split();
+
+    static FRAME_READY_SIGNAL: StaticCell<Signal<CriticalSectionRawMutex, ()>> = StaticCell::new();
+    let frame_ready_signal = &*FRAME_READY_SIGNAL.init(Signal::new());
 
     // Start the second core with the NeoPixel task
     let _guard = cpu_control
@@ -170,63 +331,167 @@ async fn _main(spawner: Spawner) -> Result<!> {
             static EXECUTOR: StaticCell<esp_hal_embassy::Executor> = StaticCell::new();
             let executor = EXECUTOR.init(esp_hal_embassy::Executor::new());
             executor.run(|spawner| {
-                spawner.spawn(neopixel_task(spi, neopixel_signal)).ok();
+                spawner
+                    .spawn(neopixel_task(
+                        matrix_config,
+                        status_strip,
+                        onboard_led,
+                        frame_reader,
+                        frame_ready_signal,
+                    ))
+                    .ok();
             });
         })
         .unwrap();
 
-    static GAMESTAGE_SIGNAL: StaticCell<Signal<CriticalSectionRawMutex, GameStage>> =
+    // the game stage is followed by the render task and the display task
+    static GAMESTAGE_WATCH: StaticCell<GameStageWatch> = StaticCell::new();
+    let gamestage_watch = &*GAMESTAGE_WATCH.init(GameStageWatch::new());
+
+    static SETTINGS_SIGNAL: StaticCell<Signal<CriticalSectionRawMutex, Settings>> =
         StaticCell::new();
-    let gamestage_signal = &*GAMESTAGE_SIGNAL.init(Signal::new());
+    let settings_signal = &*SETTINGS_SIGNAL.init(Signal::new());
+
+    static IDLE_SIGNAL: StaticCell<Signal<CriticalSectionRawMutex, IdlePhase>> = StaticCell::new();
+    let idle_signal = &*IDLE_SIGNAL.init(Signal::new());
+
+    static KEYBOARD_CONNECTED_SIGNAL: StaticCell<Signal<CriticalSectionRawMutex, bool>> =
+        StaticCell::new();
+    let keyboard_connected_signal = &*KEYBOARD_CONNECTED_SIGNAL.init(Signal::new());
+
+    // mount events are rare, but a mount and unmount can follow each other quickly,
+    // so they are queued instead of signaled
+    static USB_EVENT_CHANNEL: Channel<CriticalSectionRawMutex, UsbEvent, 4> = Channel::new();
 
     // spawn the rendering task
     println!("Spawning rendering task...");
-    let spawn_result = spawner.spawn(render_task(gamestage_signal, neopixel_signal));
+    let spawn_result = spawner.spawn(render_task(
+        gamestage_watch
+            .receiver()
+            .ok_or_else(|| error_with_location!("Too many game stage receivers"))?,
+        frame_writer,
+        frame_ready_signal,
+        settings_signal,
+        idle_signal,
+        keyboard_connected_signal,
+    ));
     if let Err(e) = spawn_result {
         println!("Failed to spawn render_task: {:?}", e);
+        system_status::report_fatal_error();
+    }
+
+    // optional OLED display on I2C0, SDA on GPIO8 and SCL on GPIO9
+    println!("Spawning display task...");
+    let i2c = I2c::new(
+        peripherals.I2C0,
+        i2c::master::Config::default().with_frequency(Rate::from_khz(400)),
+    )
+    .map_err(|err| error_with_location!("Failed to initialize I2C: {:?}", err))?
+    .with_sda(peripherals.GPIO8)
+    .with_scl(peripherals.GPIO9)
+    .into_async();
+    let spawn_result = spawner.spawn(display_task(
+        i2c,
+        gamestage_watch
+            .receiver()
+            .ok_or_else(|| error_with_location!("Too many game stage receivers"))?,
+    ));
+    if let Err(e) = spawn_result {
+        println!("Failed to spawn display_task: {:?}", e);
+        system_status::report_fatal_error();
+    }
+
+    println!("Spawning keyboard LED task...");
+    let spawn_result = spawner.spawn(keyboard_leds::keyboard_leds_task(
+        gamestage_watch
+            .receiver()
+            .ok_or_else(|| error_with_location!("Too many game stage receivers"))?,
+    ));
+    if let Err(e) = spawn_result {
+        println!("Failed to spawn keyboard_leds_task: {:?}", e);
+        system_status::report_fatal_error();
     }
 
     println!("Spawning interrupt count task...");
     let spawn_result = spawner.spawn(print_interrupt_count_task());
     if let Err(e) = spawn_result {
         println!("Failed to spawn print_interrupt_count_task: {:?}", e);
+        system_status::report_fatal_error();
     }
     println!("Spawned interrupt count task");
 
-    static KEYBOARD_INPUT_SIGNAL: StaticCell<Signal<CriticalSectionRawMutex, KeyboardInput>> =
-        StaticCell::new();
-    let keyboard_input_signal: &Signal<CriticalSectionRawMutex, KeyboardInput> =
-        &*KEYBOARD_INPUT_SIGNAL.init(Signal::new());
+    let spawn_result = spawner.spawn(print_frame_metrics_task());
+    if let Err(e) = spawn_result {
+        println!("Failed to spawn print_frame_metrics_task: {:?}", e);
+        system_status::report_fatal_error();
+    }
 
-    static mut KEYBOARD_INPUT_SIGNAL_REF: Option<&Signal<CriticalSectionRawMutex, KeyboardInput>> =
-        None;
-    unsafe {
-        KEYBOARD_INPUT_SIGNAL_REF = Some(keyboard_input_signal);
+    let spawn_result = spawner.spawn(key_repeat::key_repeat_task(
+        KEY_REPEAT,
+        KEYMAP,
+        GAMEPAD_MAPPING,
+    ));
+    if let Err(e) = spawn_result {
+        println!("Failed to spawn key_repeat_task: {:?}", e);
+        system_status::report_fatal_error();
     }
 
     println!("Spawning game logic task...");
-    let spawn_result = spawner.spawn(game::game_loop(keyboard_input_signal, gamestage_signal));
+    let spawn_result = spawner.spawn(game::game_loop(
+        input_queue::receiver(),
+        gamestage_watch.sender(),
+        settings_signal,
+        idle_signal,
+        IDLE_TIMEOUTS,
+        USB_EVENT_CHANNEL.receiver(),
+        keyboard_connected_signal,
+    ));
     if let Err(e) = spawn_result {
         println!("Failed to spawn game_logic_task: {:?}", e);
+        system_status::report_fatal_error();
     }
     println!("Spawned game logic task");
 
     // This callback will be invoked for each HID report received
-    tinyusb_callbacks::set_rust_hid_report_callback(Some(|dev_addr, instance, report, len| {
-        println!(
-            "HID report received: dev_addr={}, instance={}, len={}",
-            dev_addr, instance, len
-        );
-
-        // Check if it's a keyboard report
-        let proto = unsafe { tinyusb_sys::tuh_hid_interface_protocol(dev_addr, instance) };
-        if proto == tinyusb_sys::hid_interface_protocol_enum_t::HID_ITF_PROTOCOL_KEYBOARD as u8
-            && len as usize >= core::mem::size_of::<tinyusb_sys::hid_keyboard_report_t>()
-        {
-            let report = unsafe { &*(report as *const tinyusb_sys::hid_keyboard_report_t) };
-            if let Some(key) = process_keyboard_input(report) {
-                unsafe { KEYBOARD_INPUT_SIGNAL_REF.unwrap().signal(key) };
+    tinyusb_callbacks::set_rust_hid_report_callback(Some(
+        |dev_addr, instance, protocol, report, len| {
+            println!(
+                "HID report received: dev_addr={}, instance={}, protocol={:?}, len={}",
+                dev_addr, instance, protocol, len
+            );
+
+            let report = unsafe { core::slice::from_raw_parts(report, len as usize) };
+            match protocol {
+                HidProtocol::Keyboard => {
+                    hid_keyboard::handle_report(dev_addr, instance, report, |event| {
+                        key_repeat::push_key_event(dev_addr, event)
+                    })
+                }
+                // a generic interface can have keys, e.g. the numpad of a keypad, or be a
+                // gamepad, each handler ignores the interfaces it doesn't know
+                HidProtocol::Generic => {
+                    hid_keyboard::handle_report(dev_addr, instance, report, |event| {
+                        key_repeat::push_key_event(dev_addr, event)
+                    });
+                    hid_gamepad::handle_report(dev_addr, instance, report, |event| {
+                        key_repeat::push_gamepad_event(dev_addr, event)
+                    });
+                }
+                // the game has no use for a mouse
+                HidProtocol::Mouse => {}
             }
+        },
+    ));
+
+    tinyusb_callbacks::set_rust_usb_event_callback(Some(|event| {
+        if let UsbEvent::Unmounted { dev_addr } = event {
+            hid_keyboard::device_unmounted(dev_addr);
+            hid_gamepad::device_unmounted(dev_addr);
+            key_repeat::device_unmounted(dev_addr);
+        }
+        if USB_EVENT_CHANNEL.try_send(event).is_err() {
+            println!("USB event queue full, dropped {:?}", event);
+            system_status::report_error();
         }
     }));
 
@@ -234,68 +499,22 @@ async fn _main(spawner: Spawner) -> Result<!> {
     println!("Initializing TinyUSB...");
     init_tinyusb();
     println!("TinyUSB initialized");
+    system_status::USB_READY.store(true, core::sync::atomic::Ordering::Relaxed);
 
-    // Main loop, run both tinyusb and our game tasks
+    // USB host loop: the tinyusb interrupt handler queues the events, they are processed here.
+    // Without USB traffic the loop sleeps, so the executor can idle the core.
     loop {
         unsafe {
             tinyusb_sys::tuh_task_ext(u32::MAX, false);
         }
+        tinyusb_callbacks::USB_TASK_RUNS.fetch_add(1, core::sync::atomic::Ordering::Relaxed);
 
-        embassy_futures::yield_now().await;
+        // the timeout only catches events which tinyusb queued outside of its interrupt, see
+        // `USB_POLL_FALLBACK`
+        let _ = with_timeout(USB_POLL_FALLBACK, tinyusb_callbacks::wait_for_interrupt()).await;
     }
 }
 
-fn process_keyboard_input(report: &tinyusb_sys::hid_keyboard_report_t) -> Option<KeyboardInput> {
-    for &keycode in &report.keycode {
-        if keycode == 0 {
-            // No key pressed in this slot
-            continue;
-        }
-        println!("Keycode: {:#X}", keycode);
-    }
-
-    // Check each keycode in the report
-    for &keycode in &report.keycode {
-        // Map keycodes to actual keys
-        let key = match keycode {
-            // Numpad keys for positions 1-9
-            0x59 => KeyboardInput::Numpad(1), // Numpad 1
-            0x5A => KeyboardInput::Numpad(2), // Numpad 2
-            0x5B => KeyboardInput::Numpad(3), // Numpad 3
-            0x5C => KeyboardInput::Numpad(4), // Numpad 4
-            0x5D => KeyboardInput::Numpad(5), // Numpad 5
-            0x5E => KeyboardInput::Numpad(6), // Numpad 6
-            0x5F => KeyboardInput::Numpad(7), // Numpad 7
-            0x60 => KeyboardInput::Numpad(8), // Numpad 8
-            0x61 => KeyboardInput::Numpad(9), // Numpad 9
-
-            // Number keys for positions 1-9
-            0x1E => KeyboardInput::Number(1), // 1
-            0x1F => KeyboardInput::Number(2), // 2
-            0x20 => KeyboardInput::Number(3), // 3
-            0x21 => KeyboardInput::Number(4), // 4
-            0x22 => KeyboardInput::Number(5), // 5
-            0x23 => KeyboardInput::Number(6), // 6
-            0x24 => KeyboardInput::Number(7), // 7
-            0x25 => KeyboardInput::Number(8), // 8
-            0x26 => KeyboardInput::Number(9), // 9
-
-            0x52 => KeyboardInput::ArrowUp,      // Up Arrow
-            0x51 => KeyboardInput::ArrowDown,    // Down Arrow
-            0x50 => KeyboardInput::ArrowLeft,    // Left Arrow
-            0x4F => KeyboardInput::ArrowRight,   // Right Arrow
-            0x58 | 0x28 => KeyboardInput::Enter, // Enter key
-
-            _ => continue, // Ignore other keys
-        };
-
-        println!("Key pressed: {:?}", key);
-
-        return Some(key);
-    }
-    None
-}
-
 // Set up the USB peripheral
 fn setup_usb_peripheral() {
     unsafe {
@@ -412,6 +631,12 @@ fn init_tinyusb() {
 
         rh_init.speed = tinyusb_sys::tusb_speed_t::TUSB_SPEED_FULL as _;
 
+        // read the reports as described by the devices, so NKRO keyboards report all keys
+        // (see hid_keyboard.rs)
+        tinyusb_sys::tuh_hid_set_default_protocol(
+            tinyusb_sys::hid_protocol_mode_enum_t::HID_PROTOCOL_REPORT as u8,
+        );
+
         // Initialize tinyusb host on root port 0 through the rhport initializer
         let ok = tinyusb_sys::tuh_rhport_init(
             0u8 as _,
@@ -0,0 +1,63 @@
+use embedded_storage::{ReadStorage, Storage};
+use esp_bootloader_esp_idf::partitions;
+use esp_println::println;
+use esp_storage::FlashStorage;
+use game_core::settings::{RECORD_LEN, Settings};
+
+/// Loading and saving the settings in the 'nvs' partition of the flash,
+/// the record itself is defined in `game_core::settings`
+pub trait StoredSettings: Sized {
+    fn load() -> Self;
+    fn save(&self);
+}
+
+impl StoredSettings for Settings {
+    /// Load the settings from flash. Falls back to the defaults if nothing (valid) was stored.
+    fn load() -> Self {
+        let mut bytes = [0u8; RECORD_LEN];
+        let result = with_settings_partition(|partition| {
+            partition
+                .read(0, &mut bytes)
+                .map_err(|e| println!("Failed to read settings: {:?}", e))
+        });
+
+        match result.and_then(|_| Self::from_bytes(&bytes).ok_or(())) {
+            Ok(settings) => settings,
+            Err(_) => {
+                println!("No valid settings stored, using defaults");
+                Settings::default()
+            }
+        }
+    }
+
+    /// Store the settings in flash.
+    /// This erases a whole flash sector, so only call it when something actually changed.
+    fn save(&self) {
+        let bytes = self.to_bytes();
+        let _ = with_settings_partition(|partition| {
+            partition
+                .write(0, &bytes)
+                .map_err(|e| println!("Failed to write settings: {:?}", e))
+        });
+    }
+}
+
+/// Find the 'nvs' partition and run `f` on it
+fn with_settings_partition(
+    f: impl FnOnce(&mut partitions::FlashRegion<'_, FlashStorage>) -> Result<(), ()>,
+) -> Result<(), ()> {
+    let mut flash = FlashStorage::new();
+    let mut table_buffer = [0u8; partitions::PARTITION_TABLE_MAX_LEN];
+
+    let table = partitions::read_partition_table(&mut flash, &mut table_buffer)
+        .map_err(|e| println!("Failed to read partition table: {:?}", e))?;
+    let nvs = table
+        .find_partition(partitions::PartitionType::Data(
+            partitions::DataPartitionSubType::Nvs,
+        ))
+        .map_err(|e| println!("Failed to search partition table: {:?}", e))?
+        .ok_or_else(|| println!("No nvs partition found"))?;
+
+    let mut partition = nvs.as_embedded_storage(&mut flash);
+    f(&mut partition)
+}
@@ -0,0 +1,34 @@
+//! Flags about the state of the whole system, shown by the onboard status LED.
+//! They can be set from any task or callback.
+
+use core::{
+    cell::Cell,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use critical_section::Mutex;
+use embassy_time::Instant;
+
+/// the TinyUSB host stack is initialized
+pub static USB_READY: AtomicBool = AtomicBool::new(false);
+/// a part of the system failed to start, the details are in the log
+pub static FATAL_ERROR: AtomicBool = AtomicBool::new(false);
+/// when the last error which may go away happened, e.g. a failed USB transfer
+static LAST_ERROR: Mutex<Cell<Option<Instant>>> = Mutex::new(Cell::new(None));
+
+/// Show an error on the status LED until the next boot, in addition to the message in the log.
+/// Only for failures during the start, which don't go away by themselves.
+pub fn report_fatal_error() {
+    FATAL_ERROR.store(true, Ordering::Relaxed);
+}
+
+/// Show an error on the status LED for a few seconds, in addition to the message in the log
+pub fn report_error() {
+    let now = Instant::now();
+    critical_section::with(|cs| LAST_ERROR.borrow(cs).set(Some(now)));
+}
+
+/// When `report_error` was called last, None if it never was
+pub fn last_error() -> Option<Instant> {
+    critical_section::with(|cs| LAST_ERROR.borrow(cs).get())
+}
@@ -3,6 +3,7 @@ use core::ffi::c_void;
 use core::sync::atomic::AtomicBool;
 use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
 
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
 use esp_hal::interrupt::Priority;
 use esp_hal::system::Cpu;
 use esp_println::{print, println};
@@ -15,6 +16,11 @@ static TUSB_HANDLER_ARG: AtomicUsize = AtomicUsize::new(0);
 static TUSB_BOUND: AtomicBool = AtomicBool::new(false);
 
 pub static INTERRUPT_COUNTER: AtomicU32 = AtomicU32::new(0);
+/// Number of times the USB host loop processed the tinyusb event queue
+pub static USB_TASK_RUNS: AtomicU32 = AtomicU32::new(0);
+
+/// Set by the interrupt after the tinyusb handler queued its events, wakes the USB host loop
+static USB_INTERRUPT_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
 
 pub extern "C" fn interrupt_trampoline() {
     unsafe {
@@ -29,6 +35,12 @@ pub extern "C" fn interrupt_trampoline() {
         let handler: extern "C" fn(*mut c_void) = core::mem::transmute(h);
         handler(arg);
     }
+    USB_INTERRUPT_SIGNAL.signal(());
+}
+
+/// Wait for the next USB interrupt, returns right away if there was one since the last call
+pub async fn wait_for_interrupt() {
+    USB_INTERRUPT_SIGNAL.wait().await;
 }
 
 #[unsafe(no_mangle)]
@@ -94,6 +106,43 @@ pub extern "C" fn tusb_time_millis_api() -> u32 {
     program_start.elapsed().as_millis() as u32
 }
 
+/// A USB device was attached or removed
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum UsbEvent {
+    /// `input_device` is true if one of its HID interfaces has keys or is a gamepad
+    /// (see `hid_keyboard` and `hid_gamepad`)
+    Mounted {
+        dev_addr: u8,
+        vid: u16,
+        pid: u16,
+        input_device: bool,
+    },
+    Unmounted {
+        dev_addr: u8,
+    },
+}
+
+pub type UsbEventCallback = fn(event: UsbEvent);
+static RUST_USB_EVENT_HANDLER: AtomicUsize = AtomicUsize::new(0);
+
+pub fn set_rust_usb_event_callback(cb: Option<UsbEventCallback>) -> Option<UsbEventCallback> {
+    let prev = RUST_USB_EVENT_HANDLER.swap(cb.map(|f| f as usize).unwrap_or(0), Ordering::SeqCst);
+    if prev == 0 {
+        None
+    } else {
+        // SAFETY: we only store function pointers of type `UsbEventCallback` here.
+        Some(unsafe { core::mem::transmute(prev) })
+    }
+}
+
+fn forward_usb_event(event: UsbEvent) {
+    let rust_h = RUST_USB_EVENT_HANDLER.load(Ordering::SeqCst);
+    if rust_h != 0 {
+        let cb: UsbEventCallback = unsafe { core::mem::transmute(rust_h) };
+        cb(event);
+    }
+}
+
 /// Callback invoked by tinyusb when a device is mounted.
 /// The symbol name must match the C callback; tinyusb will call this.
 #[unsafe(no_mangle)]
@@ -102,6 +151,20 @@ extern "C" fn tuh_mount_cb(daddr: u8) {
     // a device descriptor (best-effort).
     println!("Device mounted, address = {}", daddr);
 
+    // the HID interfaces are already mounted at this point
+    let input_device =
+        crate::hid_keyboard::has_keyboard(daddr) || crate::hid_gamepad::has_gamepad(daddr);
+    let (mut vid, mut pid) = (0, 0);
+    if !unsafe { tinyusb_sys::tuh_vid_pid_get(daddr, &mut vid, &mut pid) } {
+        println!("Failed to get VID/PID of device {}", daddr);
+    }
+    forward_usb_event(UsbEvent::Mounted {
+        dev_addr: daddr,
+        vid,
+        pid,
+        input_device,
+    });
+
     unsafe {
         // Try to fetch the device descriptor into a small stack buffer (18 bytes).
         // If binding provides tuh_descriptor_get_device_sync, call it
@@ -119,8 +182,6 @@ extern "C" fn tuh_mount_cb(daddr: u8) {
                 "Got descriptor: Device {}: ID {:04x}:{:04x}",
                 daddr, vendor, product
             );
-
-            tinyusb_sys::tuh_hid_receive_report(daddr, 0);
         } else {
             println!("Failed to get device descriptor for addr {}", daddr);
         }
@@ -183,13 +244,79 @@ extern "C" fn tuh_mount_cb(daddr: u8) {
     }
 }
 
+/// Callback invoked by tinyusb for each HID interface while the device is mounted, before
+/// `tuh_mount_cb`. tinyusb fetched the report descriptor of the interface during enumeration.
+/// Reports are requested from every interface, many keypads have a second one for the numpad
+/// or the media keys.
+#[unsafe(no_mangle)]
+extern "C" fn tuh_hid_mount_cb(daddr: u8, instance: u8, desc_report: *const u8, desc_len: u16) {
+    let protocol = HidProtocol::of(daddr, instance);
+    let count = unsafe { tinyusb_sys::tuh_hid_itf_get_count(daddr) };
+    println!(
+        "Device {}: HID interface {} of {}, protocol {:?}",
+        daddr,
+        instance + 1,
+        count,
+        protocol
+    );
+
+    let descriptor = if desc_report.is_null() {
+        &[]
+    } else {
+        unsafe { core::slice::from_raw_parts(desc_report, desc_len as usize) }
+    };
+    crate::hid_keyboard::interface_mounted(
+        daddr,
+        instance,
+        protocol == HidProtocol::Keyboard,
+        descriptor,
+    );
+    crate::hid_gamepad::interface_mounted(daddr, instance, descriptor);
+
+    if !unsafe { tinyusb_sys::tuh_hid_receive_report(daddr, instance) } {
+        println!(
+            "Error: cannot request report from device {} interface {}",
+            daddr, instance
+        );
+        crate::system_status::report_error();
+    }
+}
+
 /// Callback invoked by tinyusb when a device is unmounted.
 #[unsafe(no_mangle)]
 extern "C" fn tuh_umount_cb(daddr: u8) {
     println!("Device removed, address = {}", daddr);
+    forward_usb_event(UsbEvent::Unmounted { dev_addr: daddr });
 }
 
-pub type HidReportCallback = fn(dev_addr: u8, instance: u8, report: *const u8, len: u16);
+/// Protocol of a HID interface, the reports are routed by it
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum HidProtocol {
+    /// boot keyboard, in report protocol its reports may still differ from the boot layout
+    Keyboard,
+    /// boot mouse
+    Mouse,
+    /// anything else, e.g. a keypad, media keys or a gamepad, see its report descriptor
+    Generic,
+}
+
+impl HidProtocol {
+    pub fn of(dev_addr: u8, instance: u8) -> Self {
+        let protocol = unsafe { tinyusb_sys::tuh_hid_interface_protocol(dev_addr, instance) };
+        if protocol == tinyusb_sys::hid_interface_protocol_enum_t::HID_ITF_PROTOCOL_KEYBOARD as u8 {
+            HidProtocol::Keyboard
+        } else if protocol
+            == tinyusb_sys::hid_interface_protocol_enum_t::HID_ITF_PROTOCOL_MOUSE as u8
+        {
+            HidProtocol::Mouse
+        } else {
+            HidProtocol::Generic
+        }
+    }
+}
+
+pub type HidReportCallback =
+    fn(dev_addr: u8, instance: u8, protocol: HidProtocol, report: *const u8, len: u16);
 static RUST_HID_HANDLER: AtomicUsize = AtomicUsize::new(0);
 
 pub fn set_rust_hid_report_callback(cb: Option<HidReportCallback>) -> Option<HidReportCallback> {
@@ -213,12 +340,19 @@ pub extern "C" fn tuh_hid_report_received_cb(
     let rust_h = RUST_HID_HANDLER.load(Ordering::SeqCst);
     if rust_h != 0 {
         let cb: HidReportCallback = unsafe { core::mem::transmute(rust_h) };
-        cb(dev_addr, instance, report, len);
+        cb(
+            dev_addr,
+            instance,
+            HidProtocol::of(dev_addr, instance),
+            report,
+            len,
+        );
     }
 
     // continue to request to receive report
     if !unsafe { tinyusb_sys::tuh_hid_receive_report(dev_addr, instance) } {
         println!("Error: cannot request report");
+        crate::system_status::report_error();
     }
 
     // let proto = unsafe { tinyusb_sys::tuh_hid_interface_protocol(dev_addr, instance) };
@@ -236,6 +370,66 @@ pub extern "C" fn tuh_hid_report_received_cb(
     // }
 }
 
+/// Send an output report to a HID interface, e.g. the LEDs of a keyboard, `report_id` is 0 if
+/// the interface doesn't use report IDs. tinyusb sends `report` as it is, so with a report ID
+/// it has to start with the ID. Returns false if the transfer couldn't start, e.g. because the
+/// control endpoint of the device is busy.
+///
+/// # Safety
+///
+/// tinyusb reads `report` while the transfer runs, it has to stay valid and unchanged until
+/// `tuh_hid_set_report_complete_cb` is called for the interface.
+pub unsafe fn send_output_report(dev_addr: u8, instance: u8, report_id: u8, report: &[u8]) -> bool {
+    unsafe {
+        tinyusb_sys::tuh_hid_set_report(
+            dev_addr,
+            instance,
+            report_id,
+            tinyusb_sys::hid_report_type_t::HID_REPORT_TYPE_OUTPUT as u8,
+            report.as_ptr() as *mut c_void,
+            report.len() as u16,
+        )
+    }
+}
+
+/// Switch a HID interface to the boot protocol. False if the request couldn't be sent, the
+/// result arrives in `tuh_hid_set_protocol_complete_cb`.
+pub fn set_boot_protocol(dev_addr: u8, instance: u8) -> bool {
+    unsafe {
+        tinyusb_sys::tuh_hid_set_protocol(
+            dev_addr,
+            instance,
+            tinyusb_sys::hid_protocol_mode_enum_t::HID_PROTOCOL_BOOT as u8,
+        )
+    }
+}
+
+/// Callback invoked by tinyusb when `set_boot_protocol` finished, `protocol` is the protocol
+/// the interface uses now
+#[unsafe(no_mangle)]
+extern "C" fn tuh_hid_set_protocol_complete_cb(dev_addr: u8, instance: u8, protocol: u8) {
+    let boot = protocol == tinyusb_sys::hid_protocol_mode_enum_t::HID_PROTOCOL_BOOT as u8;
+    crate::hid_keyboard::protocol_set(dev_addr, instance, boot);
+}
+
+/// Callback invoked by tinyusb when an output report was sent, `len` is 0 if it failed
+#[unsafe(no_mangle)]
+extern "C" fn tuh_hid_set_report_complete_cb(
+    dev_addr: u8,
+    instance: u8,
+    report_id: u8,
+    _report_type: u8,
+    len: u16,
+) {
+    if len == 0 {
+        println!(
+            "Device {} instance {}: output report {} failed",
+            dev_addr, instance, report_id
+        );
+    }
+    crate::hid_keyboard::led_report_complete(dev_addr, instance, len != 0);
+}
+
 unsafe fn cstr_to_str(ptr: *const c_char) -> &'static str {
     unsafe {
         if ptr.is_null() {
@@ -0,0 +1,237 @@
+//! Host-side preview of the LED matrix rendering.
+//!
+//! Renders one of the sample game stages from `game_core::samples` and either prints it to the
+//! terminal using true-color ANSI escape codes, or writes it as PNG or animated GIF.
+
+use std::{error::Error, fs::File, io::BufWriter, process::ExitCode};
+
+use game_core::{
+    Frame, MATRIX_HEIGHT, MATRIX_WIDTH,
+    idle::{IdlePhase, render_idle},
+    rendering::{render_frame, render_keyboard_icon, xy},
+    samples,
+    theme::{ColorSettings, PlayerCoding},
+    zoom::render_with_zoom,
+};
+use rgb::RGB8;
+
+const USAGE: &str = "\
+Usage: preview <sample> [ansi [seconds] | png <file> [seconds] | gif <file> [duration]] [options]
+       preview list
+
+Options:
+    --theme <index>           color theme, see game_core::theme::THEMES
+    --coding <off|blink|shape> additional player coding
+    --zoom                    zoom into the mini-grid when selecting a cell
+    --marked-moves <0|1|2>    how many of the last moves blink
+    --idle <dimmed|attract>   idle mode, 'seconds' is then also the time since it started
+    --no-keyboard             the icon shown while no keyboard is connected, instead of the game
+
+'seconds' is the time since the game stage changed, 'duration' the length of the GIF.";
+
+/// size of one LED in the PNG/GIF output, in pixels
+const LED_SIZE: usize = 12;
+/// gap between two LEDs in the PNG/GIF output, in pixels
+const LED_GAP: usize = 4;
+const IMAGE_WIDTH: usize = MATRIX_WIDTH * (LED_SIZE + LED_GAP);
+const IMAGE_HEIGHT: usize = MATRIX_HEIGHT * (LED_SIZE + LED_GAP);
+const BACKGROUND: RGB8 = RGB8::new(16, 16, 16);
+/// The LEDs are much brighter than a screen at the same value, so scale them up
+const SCREEN_GAIN: f32 = 3.0;
+
+const GIF_FPS: u16 = 25;
+
+enum Output {
+    Ansi { seconds: f32 },
+    Png { file: String, seconds: f32 },
+    Gif { file: String, duration: f32 },
+}
+
+fn main() -> ExitCode {
+    match run(std::env::args().skip(1).collect()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e}\n\n{USAGE}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let mut color_settings = ColorSettings::default();
+    let mut zoom = false;
+    let mut idle = IdlePhase::Active;
+    let mut no_keyboard = false;
+    let mut positional = Vec::new();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--theme" => {
+                let theme = args.next().ok_or("missing value for --theme")?.parse()?;
+                color_settings = ColorSettings {
+                    coding: color_settings.coding,
+                    marked_moves: color_settings.marked_moves,
+                    ..ColorSettings::with_theme(theme)
+                };
+            }
+            "--coding" => {
+                color_settings.coding = match args.next().as_deref() {
+                    Some("off") => PlayerCoding::Off,
+                    Some("blink") => PlayerCoding::Blink,
+                    Some("shape") => PlayerCoding::Shape,
+                    _ => return Err("--coding must be one of off, blink, shape".into()),
+                }
+            }
+            "--zoom" => zoom = true,
+            "--no-keyboard" => no_keyboard = true,
+            "--idle" => {
+                idle = match args.next().as_deref() {
+                    Some("dimmed") => IdlePhase::Dimmed,
+                    Some("attract") => IdlePhase::Attract,
+                    _ => return Err("--idle must be one of dimmed, attract".into()),
+                }
+            }
+            "--marked-moves" => {
+                color_settings.marked_moves = match args.next().as_deref() {
+                    Some("0") => 0,
+                    Some("1") => 1,
+                    Some("2") => 2,
+                    _ => return Err("--marked-moves must be 0, 1 or 2".into()),
+                }
+            }
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return Ok(());
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    let mut positional = positional.into_iter();
+    let sample = positional.next().ok_or("missing sample name")?;
+    if sample == "list" {
+        for (name, _) in samples::all() {
+            println!("{name}");
+        }
+        return Ok(());
+    }
+    let stage = samples::by_name(&sample).ok_or_else(|| format!("unknown sample '{sample}'"))?;
+
+    let output = match positional.next().as_deref() {
+        None | Some("ansi") => Output::Ansi {
+            seconds: parse_time(positional.next(), 0.0)?,
+        },
+        Some("png") => Output::Png {
+            file: positional.next().ok_or("missing output file")?,
+            seconds: parse_time(positional.next(), 0.0)?,
+        },
+        Some("gif") => Output::Gif {
+            file: positional.next().ok_or("missing output file")?,
+            duration: parse_time(positional.next(), 4.0)?,
+        },
+        Some(other) => return Err(format!("unknown output format '{other}'").into()),
+    };
+
+    let render = |seconds: f32| {
+        let frame = if no_keyboard {
+            render_keyboard_icon(seconds)
+        } else if zoom {
+            render_with_zoom(&stage, &stage, &color_settings, seconds)
+        } else {
+            render_frame(&stage, &color_settings, seconds)
+        };
+        render_idle(&frame, idle, seconds)
+    };
+    match output {
+        Output::Ansi { seconds } => print!("{}", to_ansi(&render(seconds))),
+        Output::Png { file, seconds } => write_png(&file, &render(seconds))?,
+        Output::Gif { file, duration } => write_gif(&file, render, duration)?,
+    }
+    Ok(())
+}
+
+fn parse_time(arg: Option<String>, default: f32) -> Result<f32, Box<dyn Error>> {
+    Ok(arg.map(|s| s.parse()).transpose()?.unwrap_or(default))
+}
+
+/// The LED at x, y. The frame is in strip order, see `xy`.
+fn led(frame: &Frame, x: usize, y: usize) -> RGB8 {
+    let mut frame = *frame;
+    *xy(&mut frame, x, y)
+}
+
+/// Every LED becomes two characters wide, so it looks roughly square in a terminal
+fn to_ansi(frame: &Frame) -> String {
+    let mut out = String::new();
+    for y in 0..MATRIX_HEIGHT {
+        for x in 0..MATRIX_WIDTH {
+            let c = screen_color(led(frame, x, y));
+            out += &format!("\x1b[48;2;{};{};{}m  ", c.r, c.g, c.b);
+        }
+        out += "\x1b[0m\n";
+    }
+    out
+}
+
+fn screen_color(led: RGB8) -> RGB8 {
+    let scale = |v: u8| (v as f32 * SCREEN_GAIN).min(255.0) as u8;
+    RGB8::new(scale(led.r), scale(led.g), scale(led.b))
+}
+
+/// Render the frame as RGB image, with a small gap between the LEDs
+fn to_image(frame: &Frame) -> Vec<u8> {
+    let mut image = Vec::with_capacity(IMAGE_WIDTH * IMAGE_HEIGHT * 3);
+    for py in 0..IMAGE_HEIGHT {
+        for px in 0..IMAGE_WIDTH {
+            let (x, y) = (px / (LED_SIZE + LED_GAP), py / (LED_SIZE + LED_GAP));
+            let in_gap =
+                px % (LED_SIZE + LED_GAP) >= LED_SIZE || py % (LED_SIZE + LED_GAP) >= LED_SIZE;
+            let c = if in_gap {
+                BACKGROUND
+            } else {
+                screen_color(led(frame, x, y))
+            };
+            image.extend_from_slice(&[c.r, c.g, c.b]);
+        }
+    }
+    image
+}
+
+fn write_png(file: &str, frame: &Frame) -> Result<(), Box<dyn Error>> {
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(file)?),
+        IMAGE_WIDTH as u32,
+        IMAGE_HEIGHT as u32,
+    );
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&to_image(frame))?;
+    Ok(())
+}
+
+fn write_gif(
+    file: &str,
+    render: impl Fn(f32) -> Frame,
+    duration: f32,
+) -> Result<(), Box<dyn Error>> {
+    let mut encoder = gif::Encoder::new(
+        BufWriter::new(File::create(file)?),
+        IMAGE_WIDTH as u16,
+        IMAGE_HEIGHT as u16,
+        &[],
+    )?;
+    encoder.set_repeat(gif::Repeat::Infinite)?;
+
+    let frame_count = (duration * GIF_FPS as f32) as usize;
+    for i in 0..frame_count {
+        let seconds = i as f32 / GIF_FPS as f32;
+        let image = to_image(&render(seconds));
+        let mut frame =
+            gif::Frame::from_rgb_speed(IMAGE_WIDTH as u16, IMAGE_HEIGHT as u16, &image, 10);
+        // in units of 10 ms
+        frame.delay = 100 / GIF_FPS;
+        encoder.write_frame(&frame)?;
+    }
+    Ok(())
+}